@@ -0,0 +1,63 @@
+#[macro_use]
+extern crate criterion;
+extern crate sqpack_blue;
+
+use criterion::{Criterion, black_box};
+use sqpack_blue::sheet::SheetValue;
+use sqpack_blue::sheet::decoding::{decode_sheet_from_bytes, decode_sheet_ref};
+use sqpack_blue::sheet::encoding::{RowBuilder, encode_sheet_pages};
+use sqpack_blue::sheet::ex::{BasicInfo, SheetDataType, SheetInfo, SheetLanguage, SheetPage, StringInfo};
+use sqpack_blue::sheet::sestring::SeString;
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+const ROWS: usize = 40000;
+const PAGE_SIZE: u32 = 500;
+
+/// A sheet shaped roughly like `Item`: a name, a few numbers, spread over many pages.
+fn synthetic_sheet() -> (SheetInfo, Vec<Vec<u8>>) {
+    let mut languages = HashSet::new();
+    languages.insert(SheetLanguage::English);
+    let info = SheetInfo {
+        data_types: vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 16 }),
+            SheetDataType::UInt(BasicInfo { pointer: 4 }),
+            SheetDataType::UShort(BasicInfo { pointer: 8 }),
+            SheetDataType::Float(BasicInfo { pointer: 12 }),
+        ],
+        pages: (0..ROWS as u32 / PAGE_SIZE).map(|p| SheetPage { page_entry: p * PAGE_SIZE, page_size: PAGE_SIZE }).collect(),
+        languages,
+        num_entries: ROWS as u32,
+        data_set_size: 16,
+        variant: 1
+    };
+    let types = Rc::new(info.data_types.clone());
+    let rows: Vec<(usize, Vec<u8>)> = (0..ROWS).map(|id| {
+        let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+        builder.set(0, &SheetValue::String(SeString::from(format!("Item number {}", id).as_str()))).unwrap();
+        builder.set(1, &SheetValue::UInt(id as u32 * 3)).unwrap();
+        builder.set(2, &SheetValue::UShort(id as u16)).unwrap();
+        builder.set(3, &SheetValue::Float(id as f32 / 2.0)).unwrap();
+        (id, builder.build())
+    }).collect();
+    let pages = encode_sheet_pages(&info, rows.iter().map(|(id, by)| (*id, by.as_slice()))).unwrap();
+    (info, pages)
+}
+
+fn scan(c: &mut Criterion) {
+    let (info, pages) = synthetic_sheet();
+    let mut group = c.benchmark_group("scan u32 column");
+    group.bench_function("owned rows", |b| b.iter(|| {
+        let sheet = decode_sheet_from_bytes(&info, black_box(&pages)).unwrap();
+        sheet.rows.values().map(|row| row.get::<u32>(1).unwrap() as u64).sum::<u64>()
+    }));
+    group.bench_function("borrowed rows", |b| b.iter(|| {
+        let rows = decode_sheet_ref(&info, black_box(&pages)).unwrap();
+        rows.values().map(|row| row.get::<u32>(1).unwrap() as u64).sum::<u64>()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, scan);
+criterion_main!(benches);
@@ -0,0 +1,76 @@
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use syn::{Data, DeriveInput, Fields, Lit, spanned::Spanned};
+
+/// Derives `sqpack_blue::sheet::FromSheetRow` for a struct. Every field needs a
+/// `#[column(n)]` attribute giving the cell index, or `#[column("Name")]` giving a
+/// column name from the sheet's schema. Field types must implement `FromSheet`.
+#[proc_macro_derive(SheetRow, attributes(column))]
+pub fn derive_sheet_row(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into()
+    }
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(syn::Error::new(input.span(), "SheetRow can only be derived for structs"))
+    };
+
+    let body = match fields {
+        Fields::Named(named) => {
+            let assignments = named.named.iter().map(|field| {
+                let ident = &field.ident;
+                let key = column_key(field)?;
+                Ok(quote! { #ident: row.get(#key)? })
+            }).collect::<Result<Vec<_>, syn::Error>>()?;
+            quote! { #name { #(#assignments),* } }
+        },
+        Fields::Unnamed(unnamed) => {
+            let values = unnamed.unnamed.iter().map(|field| {
+                let key = column_key(field)?;
+                Ok(quote! { row.get(#key)? })
+            }).collect::<Result<Vec<_>, syn::Error>>()?;
+            quote! { #name ( #(#values),* ) }
+        },
+        Fields::Unit => quote! { #name }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::sqpack_blue::sheet::FromSheetRow for #name #ty_generics #where_clause {
+            fn from_sheet_row(row: &::sqpack_blue::sheet::SheetRowRef)
+                -> ::std::result::Result<Self, ::sqpack_blue::sheet::SheetError> {
+                ::std::result::Result::Ok(#body)
+            }
+        }
+    })
+}
+
+/// Reads the `#[column(..)]` attribute of a field as either a `usize` index or a name.
+fn column_key(field: &syn::Field) -> Result<TokenStream2, syn::Error> {
+    let attr = field.attrs.iter().find(|a| a.path().is_ident("column"))
+        .ok_or_else(|| syn::Error::new(field.span(), "missing #[column(..)] attribute"))?;
+    match attr.parse_args::<Lit>()? {
+        Lit::Int(index) => {
+            let index = index.base10_parse::<usize>()?;
+            Ok(quote! { #index })
+        },
+        Lit::Str(column) => {
+            let column = column.value();
+            Ok(quote! { #column })
+        },
+        other => Err(syn::Error::new(other.span(), "expected a column index or a column name"))
+    }
+}
@@ -0,0 +1,66 @@
+extern crate sqpack_blue;
+extern crate sqpack_blue_derive;
+
+use sqpack_blue::sheet;
+use sqpack_blue::sheet::ex::{BasicInfo, SheetDataType, StringInfo};
+use sqpack_blue::sheet::schema::SheetSchema;
+use sqpack_blue::sheet::Sheet;
+use sqpack_blue_derive::SheetRow;
+
+use std::rc::Rc;
+
+#[derive(SheetRow, Debug, PartialEq)]
+struct Bgm {
+    #[column(0)]
+    file: String,
+    #[column(1)]
+    priority: u16,
+    #[column("DisableRestart")]
+    disable_restart: bool,
+}
+
+#[derive(SheetRow, Debug, PartialEq)]
+struct BgmPriority(#[column(1)] u16);
+
+fn bgm_sheet() -> Sheet {
+    let types = Rc::new(vec![
+        SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+        SheetDataType::UShort(BasicInfo { pointer: 4 }),
+        SheetDataType::Bool(BasicInfo { pointer: 6 }),
+    ]);
+    let mut sheet = Sheet { rows: Default::default(), types: types.clone(), column_count: 3, schema: None };
+    let mut by = vec![0, 0, 0, 0, 0, 7, 1, 0];
+    by.extend_from_slice(b"music/ffxiv/BGM_Null.scd\0");
+    sheet.rows.insert(2, sheet::SheetRow { by, types: types.clone(), schema: None });
+    sheet.rows.insert(3, sheet::SheetRow { by: vec![0, 0, 0, 0, 0, 1, 0, 0, 0], types: types.clone(), schema: None });
+    sheet.set_schema(&SheetSchema::from_saint_coinach_json(r#"{
+        "sheet": "BGM",
+        "definitions": [ { "name": "File" }, { "name": "Priority" }, { "name": "DisableRestart" } ]
+    }"#).unwrap());
+    sheet
+}
+
+#[test]
+fn typed_rows_from_derive() {
+    let sheet = bgm_sheet();
+    let rows: Vec<(usize, Bgm)> = sheet.typed_rows::<Bgm>().map(|(id, row)| (id, row.unwrap())).collect();
+    assert_eq!(rows, vec![
+        (2, Bgm { file: String::from("music/ffxiv/BGM_Null.scd"), priority: 7, disable_restart: true }),
+        (3, Bgm { file: String::new(), priority: 1, disable_restart: false }),
+    ]);
+}
+
+#[test]
+fn tuple_struct_and_type_errors() {
+    let sheet = bgm_sheet();
+    let priorities: Vec<u16> = sheet.typed_rows::<BgmPriority>().map(|(_, row)| row.unwrap().0).collect();
+    assert_eq!(priorities, vec![7, 1]);
+
+    #[derive(SheetRow, Debug)]
+    struct Wrong {
+        #[column(1)]
+        #[allow(dead_code)]
+        priority: u32,
+    }
+    assert!(sheet.typed_rows::<Wrong>().all(|(_, row)| row.is_err()));
+}
@@ -1,6 +1,20 @@
 extern crate byteorder;
 extern crate flate2;
 extern crate indexmap;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+#[cfg(feature = "derive")]
+extern crate sqpack_blue_derive;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "vorbis")]
+extern crate lewton;
+// Lets tests compile generated code, which names the crate by its full path
+#[cfg(test)]
+extern crate self as sqpack_blue;
 
 mod index;
 mod io;
@@ -9,7 +23,7 @@ pub mod hash;
 mod expack;
 pub mod sheet;
 
-mod scd;
+pub mod scd;
 
 pub use expack::{GameExpansion, FileType, ExFileIdentifier};
 
@@ -27,6 +41,7 @@ use std::error::Error;
 #[derive(Clone)]
 pub struct FFXIV {
     path: PathBuf,
+    schemas: sheet::schema::SchemaSet
 }
 
 #[derive(Debug)]
@@ -36,11 +51,15 @@ pub enum FFXIVError {
     ReadingDat(Box<std::error::Error>),
     DecodingEXD(Box<std::error::Error>),
     DecodingSCD(Box<std::error::Error>),
+    DecodingSchema(Box<std::error::Error>),
+    EncodingEXD(Box<std::error::Error>),
+    ExportingDatabase(Box<std::error::Error>),
     MagicMissing,
     UnknownFileType(String),
     UnknownExpansion(String),
     CorruptFileName(String),
     InvalidLanguage(sheet::ex::SheetLanguage, std::collections::HashSet<sheet::ex::SheetLanguage>),
+    NoPreferredLanguage(Vec<sheet::ex::SheetLanguage>, std::collections::HashSet<sheet::ex::SheetLanguage>),
     Custom(String),
     IO(std::io::Error),
     SheetError(sheet::SheetError)
@@ -67,11 +86,15 @@ impl std::fmt::Display for FFXIVError {
             ReadingDat(e) => write!(f, "An error occurred while parsing the dat file. Inner error: {:?}", e),
             DecodingEXD(e) => write!(f, "An error occurred while parsing the EXD file. Inner error: {:?}", e),
             DecodingSCD(e) => write!(f, "An error occurred while parsing the SCD file. Inner error: {:?}", e),
+            DecodingSchema(e) => write!(f, "An error occurred while parsing the sheet schema. Inner error: {:?}", e),
+            EncodingEXD(e) => write!(f, "An error occurred while writing the EXD file. Inner error: {:?}", e),
+            ExportingDatabase(e) => write!(f, "An error occurred while writing to the database. Inner error: {:?}", e),
             MagicMissing => write!(f, "The magic marker in a Square Enix file was missing."),
             UnknownFileType(file) => write!(f, "The type of the file was not understood. Requested file: \"{}\"", file),
             UnknownExpansion(file) => write!(f, "The expansion of the file was not understood. Requested file: \"{}\"", file),
             CorruptFileName(file) => write!(f, "Parsing of the file name failed. Requested file: \"{}\"", file),
             InvalidLanguage(req, acc) => write!(f, "The requested language was invalid! Requested: {:?}. Acceptable: {:?}", req, acc),
+            NoPreferredLanguage(req, acc) => write!(f, "None of the preferred languages are in the sheet! Preferred: {:?}. Acceptable: {:?}", req, acc),
             Custom(s) => write!(f, "{}", s),
             IO(e) => write!(f, "A problem occurred while writing: {:?}", e),
             SheetError(e) => write!(f, "An error occurred while reading the Sheet: {:?}", e),
@@ -81,12 +104,13 @@ impl std::fmt::Display for FFXIVError {
 
 impl std::error::Error for FFXIVError {}
 
+
 impl FFXIV {
     /// Create a new instance of FFXIV to manage access to the data files.
     /// Takes a path to the *sqpack directory*.
     pub fn new(path: &Path) -> Option<FFXIV> {
         if path.exists() {
-            Some(FFXIV { path: path.to_path_buf() })
+            Some(FFXIV { path: path.to_path_buf(), schemas: sheet::schema::SchemaSet::new() })
         } else {
             None
         }
@@ -146,28 +170,187 @@ impl FFXIV {
     /// Takes a parameter which is the name of the sheet (without any preceeding exd/
     /// or .exh/exd file extension)
     pub fn get_sheet(&self, exd: &String, language: sheet::ex::SheetLanguage, sheet_index: &index::SheetIndex) -> Result<sheet::Sheet, FFXIVError> {
+        let info = self.get_sheet_info(exd, language, sheet_index)?;
+        self.read_sheet(exd, &info, language, sheet_index)
+    }
+
+    /// Extracts a sheet in every language it has, so the text of each language can be
+    /// compared or exported side by side.
+    pub fn get_sheet_all_languages(&self, exd: &str, sheet_index: &index::SheetIndex) -> Result<sheet::multilang::MultiLangSheet, FFXIVError> {
+        let info = self.read_sheet_info(exd, sheet_index)?;
+        let mut sheets = Vec::with_capacity(info.languages.len());
+        for language in &info.languages {
+            sheets.push((*language, self.read_sheet(exd, &info, *language, sheet_index)?));
+        }
+        Ok(sheet::multilang::MultiLangSheet::from_sheets(sheets)?)
+    }
+
+    /// Extracts a sheet in the first language of `preferences` that the sheet has, e.g.
+    /// `[English, Japanese, None]`. Returns the sheet along with the language used.
+    pub fn get_sheet_with_fallback(&self, exd: &str, preferences: &[sheet::ex::SheetLanguage], sheet_index: &index::SheetIndex) -> Result<(sheet::Sheet, sheet::ex::SheetLanguage), FFXIVError> {
+        let info = self.read_sheet_info(exd, sheet_index)?;
+        let language = info.preferred_language(preferences)?;
+        Ok((self.read_sheet(exd, &info, language, sheet_index)?, language))
+    }
+
+    /// Sets the schemas attached to sheets loaded through a `sheet::cache::SheetCache`,
+    /// which link resolution needs, and to exported and compared sheets.
+    pub fn set_schemas(&mut self, schemas: sheet::schema::SchemaSet) {
+        self.schemas = schemas;
+    }
+
+    pub fn schemas(&self) -> &sheet::schema::SchemaSet {
+        &self.schemas
+    }
+
+    /// Gets the names of every sheet listed in `exd/root.exl`.
+    pub fn get_sheet_list(&self, sheet_index: &index::SheetIndex) -> Result<Vec<String>, FFXIVError> {
+        let exl_id = self.get_exfile(&String::from("exd/root.exl"))?;
+        let exl = self.get_raw_data_with_index(&exl_id, &sheet_index.index)?;
+        Ok(sheet::exl::decode_exl(&exl)?.into_iter().map(|e| e.name).collect())
+    }
+
+    /// Exports every sheet in `exd/root.exl` into a SQLite database, one table per sheet,
+    /// with rows for each of `languages` the sheet has. Sheets without languages are
+    /// exported once. Schemas from `set_schemas` name the columns and mark link columns,
+    /// which are indexed. Each sheet is written in its own transaction, so a sheet that
+    /// fails to read, decode or insert is left out entirely and returned with its error.
+    #[cfg(feature = "sqlite")]
+    pub fn export_sqlite(&self, conn: &mut rusqlite::Connection, languages: &[sheet::ex::SheetLanguage]) -> Result<Vec<(String, FFXIVError)>, FFXIVError> {
+        let sheet_index = self.get_sheet_index()?;
+        let mut skipped = Vec::new();
+        for name in self.get_sheet_list(&sheet_index)? {
+            let exported = self.read_sheet_languages(&name, languages, &sheet_index)
+                .and_then(|sheets| sheet::sqlite::export_sheet(conn, &name, &sheets));
+            if let Err(e) = exported {
+                skipped.push((name, e));
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Builds a full-text search index over the string columns of every sheet listed in
+    /// `exd/root.exl`, in each of `languages` the sheet has. Sheets that can't be read or
+    /// indexed are skipped and returned with the error.
+    pub fn build_search_index(&self, languages: &[sheet::ex::SheetLanguage]) -> Result<(sheet::search::SearchIndex, Vec<(String, FFXIVError)>), FFXIVError> {
+        let sheet_index = self.get_sheet_index()?;
+        let mut search_index = sheet::search::SearchIndex::new();
+        let mut skipped = Vec::new();
+        for name in self.get_sheet_list(&sheet_index)? {
+            match self.read_sheet_languages(&name, languages, &sheet_index) {
+                Ok(sheets) => if let Err(e) = search_index.add_sheets(&name, &sheets) {
+                    skipped.push((name, FFXIVError::from(e)));
+                },
+                Err(e) => skipped.push((name, e))
+            }
+        }
+        Ok((search_index, skipped))
+    }
+
+    /// Reads a sheet in each of `languages` it has, or once if it has no languages, with
+    /// the schema set by `set_schemas`.
+    fn read_sheet_languages(&self, exd: &str, languages: &[sheet::ex::SheetLanguage], sheet_index: &index::SheetIndex) -> Result<Vec<(sheet::ex::SheetLanguage, sheet::Sheet)>, FFXIVError> {
         use sheet::ex::SheetLanguage;
-        let exh_path = String::from(format!("exd/{}.exh", exd.as_str()));
-        let exfile = self.get_exfile(&exh_path)?;
-        let header = self.get_raw_data_with_index(&exfile, &sheet_index.index)?;
-        let info = sheet::decoding::decode_sheet_info(&header)?;
-        if !info.languages.contains(&language) { return Err(FFXIVError::InvalidLanguage(language, info.languages)); };
-        let mut all_page_data = Vec::<Vec<u8>>::new();
-        for page in &info.pages {
-            let exd_path = if language == SheetLanguage::None {
-                String::from(format!("exd/{}_{}.exd", exd.as_str(), page.page_entry))
-            } else {
-                String::from(format!("exd/{}_{}_{}.exd", exd.as_str(), page.page_entry, language.get_language_code().unwrap()))
-            };
-            let page_exfile = self.get_exfile(&exd_path)?;
-            let page_data = self.get_raw_data_with_index(&page_exfile, &sheet_index.index)?;
-            all_page_data.push(page_data);
+        let info = self.read_sheet_info(exd, sheet_index)?;
+        let wanted: Vec<SheetLanguage> = if info.languages.contains(&SheetLanguage::None) {
+            vec![SheetLanguage::None]
+        } else {
+            languages.iter().cloned().filter(|l| info.languages.contains(l)).collect()
+        };
+        let mut sheets = Vec::with_capacity(wanted.len());
+        for language in wanted {
+            let mut sheet = self.read_sheet(exd, &info, language, sheet_index)?;
+            if let Some(schema) = self.schemas.get(exd) {
+                sheet.set_schema(schema);
+            }
+            sheets.push((language, sheet));
         }
-        match sheet::decoding::decode_sheet_from_bytes(&info, &all_page_data) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(e)
+        Ok(sheets)
+    }
+
+    /// Compares a sheet in this installation with the same sheet in a newer one, e.g.
+    /// before and after a patch. Both are read in the first of `languages` that both
+    /// have, so a language added by the patch doesn't turn every string into a change.
+    /// Schemas set on the newer installation with `set_schemas` name changed cells.
+    pub fn diff_sheet(&self, newer: &FFXIV, exd: &str, languages: &[sheet::ex::SheetLanguage]) -> Result<sheet::diff::SheetDiff, FFXIVError> {
+        self.diff_sheet_with_index(newer, exd, languages, &self.get_sheet_index()?, &newer.get_sheet_index()?)
+    }
+
+    fn diff_sheet_with_index(&self, newer: &FFXIV, exd: &str, languages: &[sheet::ex::SheetLanguage], old_index: &index::SheetIndex, new_index: &index::SheetIndex) -> Result<sheet::diff::SheetDiff, FFXIVError> {
+        let (old_info, new_info) = (self.read_sheet_info(exd, old_index)?, newer.read_sheet_info(exd, new_index)?);
+        let shared: Vec<sheet::ex::SheetLanguage> = languages.iter().cloned().filter(|l| new_info.languages.contains(l)).collect();
+        let language = old_info.preferred_language(&shared)?;
+        let old = self.read_sheet(exd, &old_info, language, old_index)?;
+        let mut new = newer.read_sheet(exd, &new_info, language, new_index)?;
+        if let Some(schema) = newer.schemas.get(exd) {
+            new.set_schema(schema);
         }
+        Ok(sheet::diff::diff_sheets(exd, &old, &new)?)
+    }
+
+    /// Compares every sheet in `exd/root.exl` of this installation with a newer one, each
+    /// in the first of `languages` both have, as in `diff_sheet`. Schemas set on the newer installation with `set_schemas` name changed cells.
+    pub fn diff_all_sheets(&self, newer: &FFXIV, languages: &[sheet::ex::SheetLanguage]) -> Result<sheet::diff::DiffReport, FFXIVError> {
+        let (old_index, new_index) = (self.get_sheet_index()?, newer.get_sheet_index()?);
+        let old_list = self.get_sheet_list(&old_index)?;
+        let new_list = newer.get_sheet_list(&new_index)?;
+        let mut report = sheet::diff::DiffReport {
+            removed_sheets: old_list.iter().filter(|s| !new_list.contains(s)).cloned().collect(),
+            ..Default::default()
+        };
+        for name in new_list {
+            if !old_list.contains(&name) {
+                report.added_sheets.push(name);
+                continue;
+            }
+            match self.diff_sheet_with_index(newer, &name, languages, &old_index, &new_index) {
+                Ok(diff) => if !diff.is_empty() { report.sheets.push(diff) },
+                Err(e) => report.failed.push((name, e.to_string()))
+            }
+        }
+        Ok(report)
+    }
+
+    /// Like `get_sheet`, but only reads the header up front. Pages are read the first time
+    /// a row on them is requested.
+    pub fn get_lazy_sheet<'a>(&'a self, exd: &str, language: sheet::ex::SheetLanguage, sheet_index: &'a index::SheetIndex) -> Result<sheet::lazy::LazySheet<'a>, FFXIVError> {
+        let info = self.get_sheet_info(exd, language, sheet_index)?;
+        let exd = exd.to_owned();
+        Ok(sheet::lazy::LazySheet::new(info, move |page| self.get_sheet_page(&exd, page, language, sheet_index)))
+    }
+
+    /// Streams the rows of a sheet one page at a time, so huge sheets can be exported in
+    /// constant memory with `sheet::write_json_stream` or `sheet::csv::write_csv_stream`.
+    pub fn get_sheet_rows<'a>(&'a self, exd: &str, language: sheet::ex::SheetLanguage, sheet_index: &'a index::SheetIndex) -> Result<sheet::lazy::SheetRowIter<'a>, FFXIVError> {
+        let info = self.get_sheet_info(exd, language, sheet_index)?;
+        let exd = exd.to_owned();
+        Ok(sheet::lazy::SheetRowIter::new(info, move |page| self.get_sheet_page(&exd, page, language, sheet_index)))
+    }
+
+    /// Reads and decodes the EXHF header of a sheet, checking that it has the language.
+    fn get_sheet_info(&self, exd: &str, language: sheet::ex::SheetLanguage, sheet_index: &index::SheetIndex) -> Result<sheet::ex::SheetInfo, FFXIVError> {
+        let info = self.read_sheet_info(exd, sheet_index)?;
+        info.check_language(language)?;
+        Ok(info)
+    }
+
+    fn read_sheet_info(&self, exd: &str, sheet_index: &index::SheetIndex) -> Result<sheet::ex::SheetInfo, FFXIVError> {
+        let exh_path = format!("exd/{}.exh", exd);
+        let exfile = self.get_exfile(&exh_path)?;
+        let header = self.get_raw_data_with_index(&exfile, &sheet_index.index)?;
+        sheet::decoding::decode_sheet_info(&header)
+    }
+
+    /// Reads and decodes every page of a sheet in one language.
+    fn read_sheet(&self, exd: &str, info: &sheet::ex::SheetInfo, language: sheet::ex::SheetLanguage, sheet_index: &index::SheetIndex) -> Result<sheet::Sheet, FFXIVError> {
+        sheet::decoding::read_sheet_pages(info, |page| self.get_sheet_page(exd, page, language, sheet_index))
+    }
 
+    /// Reads the EXDF file of one page of a sheet.
+    fn get_sheet_page(&self, exd: &str, page: &sheet::ex::SheetPage, language: sheet::ex::SheetLanguage, sheet_index: &index::SheetIndex) -> Result<Vec<u8>, FFXIVError> {
+        let exd_path = sheet::ex::page_file_name(exd, page, language);
+        let page_exfile = self.get_exfile(&exd_path)?;
+        self.get_raw_data_with_index(&page_exfile, &sheet_index.index)
     }
 
     pub fn decode_sound(&self, data: Vec<u8>) -> Result<scd::SCDFile, FFXIVError> {
@@ -1,4 +1,5 @@
 use ::FFXIVError;
+use super::pcm::Pcm;
 
 #[derive(Debug)]
 pub struct SCDEntryHeader {
@@ -34,6 +35,12 @@ impl std::fmt::Display for SCDCodec {
 pub trait SCDEntry {
     fn create(buffer: &[u8], header: SCDEntryHeader, chunks_offset: &u32, data_offset: &u32, little_end: &bool) -> Result<Box<Self>, FFXIVError> where Self: Sized;
     fn decoded(&self) -> &Vec<u8>;
+    /// Decodes the entry into interleaved 16-bit samples.
+    fn pcm(&self) -> Result<Pcm, FFXIVError>;
+    /// Decodes the entry into a 16-bit PCM WAV file, for players that only handle PCM.
+    fn decoded_pcm(&self) -> Result<Vec<u8>, FFXIVError> {
+        self.pcm()?.to_wav()
+    }
     fn header(&self) -> &SCDEntryHeader;
 }
 
@@ -61,6 +68,10 @@ impl SCDEntry for SCDEntryNone {
         &self.decoded
     }
 
+    fn pcm(&self) -> Result<Pcm, FFXIVError> {
+        Ok(Pcm::default())
+    }
+
     fn header(&self) -> &SCDEntryHeader {
         &self.header
     }
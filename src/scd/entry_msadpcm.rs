@@ -1,5 +1,7 @@
 use ::FFXIVError;
 use super::{SCDEntry, SCDEntryHeader};
+use super::msadpcm::{AdpcmFormat, decode_msadpcm};
+use super::pcm::Pcm;
 use std::io::Write;
 use ::byteorder::{WriteBytesExt, LittleEndian};
 
@@ -7,17 +9,22 @@ use ::byteorder::{WriteBytesExt, LittleEndian};
 pub struct SCDEntryMSADPCM {
     header: SCDEntryHeader,
     decoded: Vec<u8>,
+    format: AdpcmFormat
 }
 
 const WAVE_HEADER_SIZE: usize = 0x10;
 const BEGIN_MAGIC: &'static str = "RIFF";
 const WAVE_MAGIC: &'static str = "WAVEfmt ";
 const DATA_MAGIC: &'static str = "data";
+/// Where the samples start in `decoded`, after the RIFF, fmt and data chunk headers.
+const SAMPLES_START: usize = 0x1c + WAVE_HEADER_SIZE;
 
 impl SCDEntry for SCDEntryMSADPCM {
 
     fn create(buffer: &[u8], header: SCDEntryHeader, chunks_offset: &u32, data_offset: &u32, _little_end: &bool) -> Result<Box<SCDEntryMSADPCM>, FFXIVError> {
         let final_data_offset = *chunks_offset + header.samples_offset as u32;
+        let format_end = (*data_offset as usize + header.samples_offset as usize).min(buffer.len());
+        let format = AdpcmFormat::parse(&buffer[*data_offset as usize..format_end])?;
 
         let mut decoded = Vec::<u8>::with_capacity(0x1c + WAVE_HEADER_SIZE + header.data_size as usize);
         decoded.write_all(BEGIN_MAGIC.as_bytes())
@@ -45,7 +52,7 @@ impl SCDEntry for SCDEntryMSADPCM {
             .map_err(|e| FFXIVError::DecodingSCD(Box::new(e)))?;
 
         Ok(Box::new(SCDEntryMSADPCM {
-            header, decoded
+            header, decoded, format
         }))
 
     }
@@ -54,6 +61,14 @@ impl SCDEntry for SCDEntryMSADPCM {
         &self.decoded
     }
 
+    fn pcm(&self) -> Result<Pcm, FFXIVError> {
+        Ok(Pcm {
+            channels: self.format.channels,
+            sample_rate: self.format.sample_rate,
+            samples: decode_msadpcm(&self.format, &self.decoded[SAMPLES_START..])?
+        })
+    }
+
     fn header(&self) -> &SCDEntryHeader {
         &self.header
     }
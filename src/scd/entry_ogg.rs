@@ -1,6 +1,7 @@
 use super::SCDEntry;
 use super::SCDEntryHeader;
 use super::decoding::{read_i16, read_i32};
+use super::pcm::Pcm;
 use ::FFXIVError;
 
 use std::io::Write;
@@ -84,6 +85,18 @@ impl SCDEntry for SCDEntryOgg {
         &self.decoded
     }
 
+    /// Decodes the Vorbis stream. Needs the `vorbis` feature.
+    #[cfg(feature = "vorbis")]
+    fn pcm(&self) -> Result<Pcm, FFXIVError> {
+        super::pcm::decode_vorbis(&self.decoded, self.header.channel_count as u16, self.header.frequency as u32)
+    }
+
+    #[cfg(not(feature = "vorbis"))]
+    fn pcm(&self) -> Result<Pcm, FFXIVError> {
+        Err(FFXIVError::DecodingSCD(Box::new(FFXIVError::Custom(String::from(
+            "Decoding Ogg Vorbis to PCM needs the vorbis feature")))))
+    }
+
     fn header(&self) -> &SCDEntryHeader {
         &self.header
     }
@@ -3,8 +3,10 @@ mod entry;
 mod entry_ogg;
 mod entry_msadpcm;
 mod decoding;
+pub mod msadpcm;
+pub mod pcm;
 
-use self::entry::*;
+pub use self::entry::{SCDCodec, SCDEntry, SCDEntryHeader};
 //use self::entry_ogg::SCDEntryOgg;
 //use self::entry_msadpcm::SCDEntryMSADPCM;
 use self::decoding::*;
@@ -0,0 +1,198 @@
+use ::FFXIVError;
+use ::byteorder::{ByteOrder, LittleEndian};
+
+/// How the step size adapts to each encoded nibble.
+const ADAPTATION: [i32; 16] = [230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230];
+
+/// The largest step size, so that adapting it can't overflow. Encoders never come near it,
+/// but a corrupt stream can keep growing the step until it would.
+const MAX_DELTA: i32 = i32::MAX / 768;
+
+/// The predictor coefficients every MS-ADPCM encoder uses, for streams whose format
+/// doesn't list its own.
+const STANDARD_COEFFICIENTS: [(i16, i16); 7] = [(256, 0), (512, -256), (0, 0), (192, 64), (240, 0), (460, -208), (392, -232)];
+
+/// Size of a WAVEFORMATEX without its extension.
+const WAVEFORMATEX_SIZE: usize = 0x12;
+
+fn adpcm_error(message: String) -> FFXIVError {
+    FFXIVError::DecodingSCD(Box::new(FFXIVError::Custom(message)))
+}
+
+/// The parts of an MS-ADPCM WAVEFORMATEX the decoder needs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdpcmFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Size in bytes of each block of encoded samples.
+    pub block_align: u16,
+    /// Samples per channel in each full block, including the two in the block header.
+    pub samples_per_block: u16,
+    pub coefficients: Vec<(i16, i16)>
+}
+
+impl AdpcmFormat {
+    /// Reads a little-endian WAVEFORMATEX. The standard coefficients are used when the
+    /// format has no MS-ADPCM extension.
+    pub fn parse(format: &[u8]) -> Result<AdpcmFormat, FFXIVError> {
+        if format.len() < 0x10 {
+            return Err(adpcm_error(format!("MS-ADPCM format is too short: {} bytes", format.len())));
+        }
+        let channels = LittleEndian::read_u16(&format[0x2..]);
+        let block_align = LittleEndian::read_u16(&format[0xc..]);
+        if channels == 0 || (block_align as usize) < 7 * channels as usize {
+            return Err(adpcm_error(format!("Invalid MS-ADPCM format: {} channels, block size {}", channels, block_align)));
+        }
+        let extension_size = if format.len() >= WAVEFORMATEX_SIZE { LittleEndian::read_u16(&format[0x10..]) as usize } else { 0 };
+        let extension = &format[WAVEFORMATEX_SIZE.min(format.len())..(WAVEFORMATEX_SIZE + extension_size).min(format.len())];
+
+        let mut samples_per_block = if extension.len() >= 2 { LittleEndian::read_u16(extension) } else { 0 };
+        if samples_per_block == 0 {
+            samples_per_block = ((block_align as usize - 7 * channels as usize) * 2 / channels as usize + 2) as u16;
+        }
+        let coefficient_count = if extension.len() >= 4 { LittleEndian::read_u16(&extension[2..]) as usize } else { 0 };
+        let coefficients = if coefficient_count > 0 && extension.len() >= 4 + coefficient_count * 4 {
+            extension[4..4 + coefficient_count * 4].chunks(4)
+                .map(|c| (LittleEndian::read_i16(c), LittleEndian::read_i16(&c[2..])))
+                .collect()
+        } else {
+            STANDARD_COEFFICIENTS.to_vec()
+        };
+        Ok(AdpcmFormat { channels, sample_rate: LittleEndian::read_u32(&format[0x4..]), block_align, samples_per_block, coefficients })
+    }
+}
+
+struct ChannelState {
+    coefficient: (i16, i16),
+    delta: i32,
+    sample_1: i32,
+    sample_2: i32
+}
+
+impl ChannelState {
+    fn expand(&mut self, nibble: u8) -> i16 {
+        let signed = if nibble & 0x8 != 0 { nibble as i32 - 16 } else { nibble as i32 };
+        let predicted = (self.sample_1 * self.coefficient.0 as i32 + self.sample_2 * self.coefficient.1 as i32) >> 8;
+        let sample = (predicted + signed * self.delta).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.sample_2 = self.sample_1;
+        self.sample_1 = sample;
+        self.delta = ((ADAPTATION[nibble as usize] * self.delta) >> 8).clamp(16, MAX_DELTA);
+        sample as i16
+    }
+}
+
+/// Decodes MS-ADPCM blocks into interleaved 16-bit samples. A short final block is
+/// decoded as far as it goes.
+pub fn decode_msadpcm(format: &AdpcmFormat, data: &[u8]) -> Result<Vec<i16>, FFXIVError> {
+    let channels = format.channels as usize;
+    let header_size = 7 * channels;
+    let mut samples = Vec::with_capacity(data.len() * 2);
+    for block in data.chunks(format.block_align as usize) {
+        if block.len() < header_size {
+            break;
+        }
+        let mut states = Vec::with_capacity(channels);
+        for channel in 0..channels {
+            let predictor = block[channel] as usize;
+            let coefficient = *format.coefficients.get(predictor)
+                .ok_or_else(|| adpcm_error(format!("MS-ADPCM predictor {} is out of range", predictor)))?;
+            let field = |index: usize| LittleEndian::read_i16(&block[channels + 2 * (index * channels + channel)..]) as i32;
+            states.push(ChannelState { coefficient, delta: field(0), sample_1: field(1), sample_2: field(2) });
+        }
+        samples.extend(states.iter().map(|s| s.sample_2 as i16));
+        samples.extend(states.iter().map(|s| s.sample_1 as i16));
+
+        let nibbles = (format.samples_per_block as usize).saturating_sub(2) * channels;
+        let body = &block[header_size..];
+        for (index, nibble) in body.iter().flat_map(|b| [b >> 4, b & 0xf]).take(nibbles).enumerate() {
+            samples.push(states[index % channels].expand(nibble));
+        }
+        // Drop a partial frame at the end of a truncated block
+        samples.truncate(samples.len() - samples.len() % channels);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod msadpcm_test {
+    use super::*;
+    use ::byteorder::WriteBytesExt;
+    use super::super::{SCDCodec, SCDEntry, SCDEntryHeader};
+    use super::super::entry_msadpcm::SCDEntryMSADPCM;
+
+    fn wave_format(channels: u16, block_align: u16) -> Vec<u8> {
+        let mut format = Vec::new();
+        format.write_u16::<LittleEndian>(2).unwrap();
+        format.write_u16::<LittleEndian>(channels).unwrap();
+        format.write_u32::<LittleEndian>(44100).unwrap();
+        format.write_u32::<LittleEndian>(0).unwrap();
+        format.write_u16::<LittleEndian>(block_align).unwrap();
+        format.write_u16::<LittleEndian>(4).unwrap();
+        format.write_u16::<LittleEndian>(32).unwrap();
+        format.write_u16::<LittleEndian>(0).unwrap();
+        format.write_u16::<LittleEndian>(7).unwrap();
+        for (c1, c2) in STANDARD_COEFFICIENTS.iter() {
+            format.write_i16::<LittleEndian>(*c1).unwrap();
+            format.write_i16::<LittleEndian>(*c2).unwrap();
+        }
+        format
+    }
+
+    #[test]
+    fn mono() {
+        let format = AdpcmFormat::parse(&wave_format(1, 9)).unwrap();
+        assert_eq!(format.samples_per_block, 6);
+        assert_eq!(format.coefficients.len(), 7);
+        // Predictor 0, delta 16, sample 1 = 100, sample 2 = 50, then nibbles 1, 2, 3, -1
+        let block = [0, 16, 0, 100, 0, 50, 0, 0x12, 0x3f];
+        assert_eq!(decode_msadpcm(&format, &block).unwrap(), vec![50, 100, 116, 148, 196, 180]);
+        // A truncated second block gives its header samples and what nibbles it has
+        let mut two = block.to_vec();
+        two.extend_from_slice(&block[..8]);
+        assert_eq!(decode_msadpcm(&format, &two).unwrap().len(), 6 + 4);
+    }
+
+    #[test]
+    fn stereo() {
+        let format = AdpcmFormat::parse(&wave_format(2, 15)).unwrap();
+        // Left uses predictor 1 (512, -256), right predictor 0 (256, 0)
+        let block = [1, 0, 16, 0, 32, 0, 10, 0, 0xf6, 0xff, 0, 0, 0, 0, 0x21];
+        assert_eq!(decode_msadpcm(&format, &block).unwrap(), vec![0, 0, 10, -10, 52, 22]);
+
+        let bad = [7, 0, 16, 0, 32, 0, 10, 0, 0xf6, 0xff, 0, 0, 0, 0];
+        assert!(decode_msadpcm(&format, &bad).is_err());
+        assert!(AdpcmFormat::parse(&wave_format(2, 10)).is_err());
+    }
+
+    #[test]
+    fn step_size_is_bounded() {
+        // The largest delta, then nibbles that keep tripling it
+        let format = AdpcmFormat::parse(&wave_format(1, 7 + 0x88)).unwrap();
+        let mut block = vec![0, 0xff, 0x7f, 0, 0, 0, 0];
+        block.extend_from_slice(&[0x88; 0x88]);
+        let samples = decode_msadpcm(&format, &block).unwrap();
+        assert_eq!(samples.len(), 2 + 0x88 * 2);
+        assert!(samples[2..].iter().all(|s| *s == i16::MIN));
+    }
+
+    #[test]
+    fn entry_to_pcm_wav() {
+        let mut buffer = wave_format(1, 9);
+        let samples_offset = buffer.len() as i32;
+        buffer.extend_from_slice(&[0, 16, 0, 100, 0, 50, 0, 0x12, 0x3f]);
+        let header = SCDEntryHeader {
+            data_size: 9, channel_count: 1, frequency: 44100, codec: SCDCodec::MSADPCM,
+            loop_start: 0, loop_end: 0, samples_offset, aux_chunk_count: 0, unknown_1: 0
+        };
+        let entry = SCDEntryMSADPCM::create(&buffer, header, &0, &0, &true).unwrap();
+        assert_eq!(entry.pcm().unwrap().frames(), 6);
+        let wav = entry.decoded_pcm().unwrap();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(LittleEndian::read_u16(&wav[20..]), 1);
+        assert_eq!(LittleEndian::read_u32(&wav[24..]), 44100);
+        assert_eq!(LittleEndian::read_u16(&wav[34..]), 16);
+        assert_eq!(LittleEndian::read_u32(&wav[40..]), 12);
+        let pcm: Vec<i16> = wav[44..].chunks(2).map(LittleEndian::read_i16).collect();
+        assert_eq!(pcm, vec![50, 100, 116, 148, 196, 180]);
+    }
+}
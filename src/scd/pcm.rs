@@ -0,0 +1,155 @@
+use ::FFXIVError;
+use ::byteorder::{LittleEndian, WriteBytesExt};
+
+/// Decoded audio as interleaved 16-bit samples.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pcm {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Vec<i16>
+}
+
+impl Pcm {
+    /// The number of samples per channel.
+    pub fn frames(&self) -> usize {
+        if self.channels == 0 { 0 } else { self.samples.len() / self.channels as usize }
+    }
+
+    /// Wraps the samples in a PCM WAV file.
+    pub fn to_wav(&self) -> Result<Vec<u8>, FFXIVError> {
+        let data_size = self.samples.len() as u32 * 2;
+        let mut wav = Vec::<u8>::with_capacity(44 + data_size as usize);
+        let write = |wav: &mut Vec<u8>| -> std::io::Result<()> {
+            wav.extend_from_slice(b"RIFF");
+            wav.write_u32::<LittleEndian>(36 + data_size)?;
+            wav.extend_from_slice(b"WAVEfmt ");
+            wav.write_u32::<LittleEndian>(16)?;
+            wav.write_u16::<LittleEndian>(1)?;
+            wav.write_u16::<LittleEndian>(self.channels)?;
+            wav.write_u32::<LittleEndian>(self.sample_rate)?;
+            wav.write_u32::<LittleEndian>(self.sample_rate * self.channels as u32 * 2)?;
+            wav.write_u16::<LittleEndian>(self.channels * 2)?;
+            wav.write_u16::<LittleEndian>(16)?;
+            wav.extend_from_slice(b"data");
+            wav.write_u32::<LittleEndian>(data_size)?;
+            for sample in &self.samples {
+                wav.write_i16::<LittleEndian>(*sample)?;
+            }
+            Ok(())
+        };
+        write(&mut wav).map_err(|e| FFXIVError::DecodingSCD(Box::new(e)))?;
+        Ok(wav)
+    }
+}
+
+/// Decodes an Ogg Vorbis stream. The stream must have the channel count and sample rate
+/// the SCD entry header gives.
+#[cfg(feature = "vorbis")]
+pub fn decode_vorbis(ogg: &[u8], channels: u16, sample_rate: u32) -> Result<Pcm, FFXIVError> {
+    use lewton::inside_ogg::OggStreamReader;
+
+    let vorbis_error = |e: lewton::VorbisError| FFXIVError::DecodingSCD(Box::new(e));
+    let mut reader = OggStreamReader::new(std::io::Cursor::new(ogg)).map_err(vorbis_error)?;
+    let (stream_channels, stream_rate) = (reader.ident_hdr.audio_channels as u16, reader.ident_hdr.audio_sample_rate);
+    if stream_channels != channels || stream_rate != sample_rate {
+        return Err(FFXIVError::DecodingSCD(Box::new(FFXIVError::Custom(format!(
+            "Vorbis stream has {} channels at {} Hz, but the entry header gives {} channels at {} Hz",
+            stream_channels, stream_rate, channels, sample_rate)))));
+    }
+    let mut samples = Vec::new();
+    while let Some(packet) = reader.read_dec_packet_itl().map_err(vorbis_error)? {
+        samples.extend_from_slice(&packet);
+    }
+    Ok(Pcm { channels, sample_rate, samples })
+}
+
+#[cfg(test)]
+mod pcm_test {
+    use super::*;
+    use ::byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn wav() {
+        let pcm = Pcm { channels: 2, sample_rate: 48000, samples: vec![1, -1, 300, -300] };
+        assert_eq!(pcm.frames(), 2);
+        let wav = pcm.to_wav().unwrap();
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(LittleEndian::read_u32(&wav[4..]), 44);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(LittleEndian::read_u16(&wav[20..]), 1);
+        assert_eq!(LittleEndian::read_u16(&wav[22..]), 2);
+        assert_eq!(LittleEndian::read_u32(&wav[28..]), 48000 * 4);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(LittleEndian::read_i16(&wav[48..]), 300);
+        assert_eq!(Pcm::default().frames(), 0);
+    }
+
+    #[test]
+    fn ogg_entry() {
+        use super::super::{SCDCodec, SCDEntry, SCDEntryHeader};
+        use super::super::entry_ogg::SCDEntryOgg;
+        // An unencrypted entry with no seek table or Vorbis header, holding bytes that
+        // aren't a Vorbis stream
+        let mut buffer = vec![0u8; 0x20];
+        buffer.extend_from_slice(b"not vorbis");
+        let header = SCDEntryHeader {
+            data_size: 10, channel_count: 2, frequency: 44100, codec: SCDCodec::OGG,
+            loop_start: 0, loop_end: 0, samples_offset: 0, aux_chunk_count: 0, unknown_1: 0
+        };
+        let entry = SCDEntryOgg::create(&buffer, header, &0, &0, &true).unwrap();
+        assert_eq!(entry.decoded(), b"not vorbis");
+        match entry.decoded_pcm() {
+            Err(FFXIVError::DecodingSCD(_)) => (),
+            other => panic!("expected a decoding error, got {:?}", other)
+        }
+    }
+
+    #[cfg(feature = "vorbis")]
+    #[test]
+    fn vorbis_entry() {
+        use super::super::{SCDCodec, SCDEntry, SCDEntryHeader};
+        use super::super::entry_ogg::SCDEntryOgg;
+        // A minimal mono stream: five identical 64-sample blocks of a flat spectrum
+        let ogg = include_bytes!("vorbis.ogg");
+        let mut buffer = vec![0u8; 0x20];
+        buffer.extend_from_slice(ogg);
+        let header = SCDEntryHeader {
+            data_size: ogg.len() as i32, channel_count: 1, frequency: 44100, codec: SCDCodec::OGG,
+            loop_start: 0, loop_end: 0, samples_offset: 0, aux_chunk_count: 0, unknown_1: 0
+        };
+        let entry = SCDEntryOgg::create(&buffer, header, &0, &0, &true).unwrap();
+        let pcm = entry.pcm().unwrap();
+        assert_eq!((pcm.channels, pcm.sample_rate), (1, 44100));
+        // The first block only primes the overlap, and each block after it adds 32 samples
+        assert_eq!(pcm.frames(), 128);
+        assert!(pcm.samples.iter().any(|s| *s != 0));
+        assert_eq!(pcm.samples[..32], pcm.samples[96..]);
+        let wav = entry.decoded_pcm().unwrap();
+        assert_eq!(wav.len(), 44 + 256);
+        assert_eq!(LittleEndian::read_i16(&wav[44..]), pcm.samples[0]);
+    }
+
+    #[test]
+    fn none_entry() {
+        use super::super::{SCDEntry, SCDEntryHeader};
+        use super::super::entry::{SCDCodec, SCDEntryNone};
+        let header = SCDEntryHeader {
+            data_size: 0, channel_count: 0, frequency: 0, codec: SCDCodec::None,
+            loop_start: 0, loop_end: 0, samples_offset: 0, aux_chunk_count: 0, unknown_1: 0
+        };
+        let wav = SCDEntryNone::create(&[], header, &0, &0, &true).unwrap().decoded_pcm().unwrap();
+        assert_eq!(wav.len(), 44);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(LittleEndian::read_u32(&wav[40..]), 0);
+    }
+
+    #[cfg(feature = "vorbis")]
+    #[test]
+    fn vorbis_rejects_garbage() {
+        match decode_vorbis(b"OggS but not really", 2, 44100) {
+            Err(FFXIVError::DecodingSCD(_)) => (),
+            other => panic!("expected a decoding error, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use super::Sheet;
+use super::ex::SheetLanguage;
+use super::link::SheetProvider;
+use ::index::SheetIndex;
+use ::{FFXIV, FFXIVError};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Sheets loaded from an installation for link resolution, each read once with its
+/// schema from `FFXIV::set_schemas` attached. The cache lives apart from `FFXIV` so that
+/// `FFXIV` stays `Send` and `Sync`; make one per thread.
+pub struct SheetCache<'a> {
+    ffxiv: &'a FFXIV,
+    languages: Vec<SheetLanguage>,
+    index: RefCell<Option<Rc<SheetIndex>>>,
+    sheets: RefCell<HashMap<String, Rc<Sheet>>>
+}
+
+impl<'a> SheetCache<'a> {
+    /// Creates a cache loading sheets in English, or without a language for sheets that
+    /// have none.
+    pub fn new(ffxiv: &'a FFXIV) -> SheetCache<'a> {
+        SheetCache::with_languages(ffxiv, &[SheetLanguage::English, SheetLanguage::None])
+    }
+
+    /// Creates a cache loading sheets in the first of `preferences` each sheet has.
+    pub fn with_languages(ffxiv: &'a FFXIV, preferences: &[SheetLanguage]) -> SheetCache<'a> {
+        SheetCache { ffxiv, languages: preferences.to_vec(), index: RefCell::new(None), sheets: RefCell::new(HashMap::new()) }
+    }
+
+    /// Gets a sheet, loading it on first use.
+    pub fn get(&self, exd: &str) -> Result<Rc<Sheet>, FFXIVError> {
+        let key = exd.to_ascii_lowercase();
+        if let Some(sheet) = self.sheets.borrow().get(&key) {
+            return Ok(sheet.clone());
+        }
+        // Bound first so the borrow ends before the index is stored
+        let cached = self.index.borrow().clone();
+        let sheet_index = match cached {
+            Some(sheet_index) => sheet_index,
+            None => {
+                let sheet_index = Rc::new(self.ffxiv.get_sheet_index()?);
+                *self.index.borrow_mut() = Some(sheet_index.clone());
+                sheet_index
+            }
+        };
+        let (mut sheet, _) = self.ffxiv.get_sheet_with_fallback(exd, &self.languages, &sheet_index)?;
+        if let Some(schema) = self.ffxiv.schemas().get(exd) {
+            sheet.set_schema(schema);
+        }
+        let sheet = Rc::new(sheet);
+        self.sheets.borrow_mut().insert(key, sheet.clone());
+        Ok(sheet)
+    }
+
+    pub fn clear(&self) {
+        self.sheets.borrow_mut().clear();
+    }
+}
+
+impl<'a> SheetProvider for SheetCache<'a> {
+    fn sheet(&self, name: &str) -> Result<Rc<Sheet>, FFXIVError> {
+        self.get(name)
+    }
+}
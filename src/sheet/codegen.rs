@@ -0,0 +1,263 @@
+use super::ex::{SheetDataType, SheetInfo};
+use super::decoding::decode_sheet_info;
+use super::schema::{SchemaSet, SheetSchema};
+use ::FFXIVError;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Strict and reserved keywords of every edition, which field names must avoid.
+const KEYWORDS: [&str; 51] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "self", "static",
+    "struct", "super", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield"
+];
+
+/// Keywords that can't be raw identifiers.
+const PATH_KEYWORDS: [&str; 3] = ["crate", "self", "super"];
+
+/// The Rust type a column is read into.
+fn field_type(data_type: &SheetDataType) -> &'static str {
+    match data_type {
+        SheetDataType::String(_) => "::sqpack_blue::sheet::sestring::SeString",
+        SheetDataType::Bool(_) | SheetDataType::BitFlags(_) => "bool",
+        SheetDataType::Byte(_) => "i8",
+        SheetDataType::UByte(_) => "u8",
+        SheetDataType::Short(_) => "i16",
+        SheetDataType::UShort(_) => "u16",
+        SheetDataType::Int(_) => "i32",
+        SheetDataType::UInt(_) => "u32",
+        SheetDataType::Float(_) => "f32",
+        SheetDataType::PackedInts(_) => "u64"
+    }
+}
+
+/// Converts a schema column name to a snake_case field name, e.g. `BaseParam[2]` to
+/// `base_param_2`. Keywords become raw identifiers, e.g. `r#type`, except `crate`,
+/// `self` and `super`, which get a trailing `_`.
+pub fn field_name(column: &str) -> String {
+    let mut name = String::new();
+    let mut previous: Option<char> = None;
+    for c in column.chars() {
+        if c.is_ascii_alphanumeric() {
+            let boundary = match previous {
+                Some(p) => c.is_ascii_uppercase() && (p.is_ascii_lowercase() || p.is_ascii_digit()),
+                None => false
+            };
+            if boundary && !name.ends_with('_') {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+        previous = Some(c);
+    }
+    let name = String::from(name.trim_end_matches('_'));
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("column_{}", name)
+    } else if PATH_KEYWORDS.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else if KEYWORDS.contains(&name.as_str()) {
+        format!("r#{}", name)
+    } else {
+        name
+    }
+}
+
+/// Converts a sheet name to a CamelCase struct name, e.g. `quest/001/ClsHyu001_00194`
+/// to `Quest001ClsHyu00100194`.
+pub fn struct_name(sheet: &str) -> String {
+    let mut name = String::new();
+    for part in sheet.split(|c: char| !c.is_ascii_alphanumeric()).filter(|p| !p.is_empty()) {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("Sheet{}", name)
+    } else {
+        name
+    }
+}
+
+/// Generates a struct for a sheet, with one field per column and a `FromSheetRow` impl.
+/// Fields are named from the schema where it names the column and `column_<index>`
+/// otherwise. Cells are read by index, so the struct matches this header's layout.
+pub fn generate_struct(sheet: &str, info: &SheetInfo, schema: Option<&SheetSchema>) -> String {
+    let schema = schema.map(|s| s.resolve(&info.data_types));
+    let name = struct_name(sheet);
+    let mut fields = Vec::<String>::with_capacity(info.data_types.len());
+    for column in 0..info.data_types.len() {
+        let field = match schema.as_ref().and_then(|s| s.column_name(column)) {
+            Some(column_name) => field_name(column_name),
+            None => format!("column_{}", column)
+        };
+        fields.push(if fields.contains(&field) { format!("{}_{}", field, column) } else { field });
+    }
+
+    let mut code = String::new();
+    writeln!(code, "/// A row of the `{}` sheet.", sheet).unwrap();
+    writeln!(code, "#[derive(Clone, Debug, PartialEq)]").unwrap();
+    writeln!(code, "pub struct {} {{", name).unwrap();
+    for (column, (field, data_type)) in fields.iter().zip(&info.data_types).enumerate() {
+        let original = schema.as_ref().and_then(|s| s.column_name(column));
+        match original {
+            Some(original) => writeln!(code, "    /// `{}`, column {}", original, column).unwrap(),
+            None => writeln!(code, "    /// Column {}", column).unwrap()
+        }
+        writeln!(code, "    pub {}: {},", field, field_type(data_type)).unwrap();
+    }
+    writeln!(code, "}}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "impl ::sqpack_blue::sheet::FromSheetRow for {} {{", name).unwrap();
+    writeln!(code, "    fn from_sheet_row(row: &::sqpack_blue::sheet::SheetRowRef) -> ::std::result::Result<Self, ::sqpack_blue::sheet::SheetError> {{").unwrap();
+    writeln!(code, "        ::std::result::Result::Ok({} {{", name).unwrap();
+    for (column, field) in fields.iter().enumerate() {
+        writeln!(code, "            {}: row.get({}usize)?,", field, column).unwrap();
+    }
+    writeln!(code, "        }})").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "}}").unwrap();
+    code
+}
+
+/// Generates structs for several sheets from their EXHF headers. Schemas are looked up
+/// by sheet name.
+pub fn generate_module(sheets: &[(String, Vec<u8>)], schemas: Option<&SchemaSet>) -> Result<String, FFXIVError> {
+    let mut code = String::from("// Generated by sqpack_blue::sheet::codegen. Do not edit.\n");
+    for (sheet, exh) in sheets {
+        let info = decode_sheet_info(exh)?;
+        code.push('\n');
+        code.push_str(&generate_struct(sheet, &info, schemas.and_then(|s| s.get(sheet))));
+    }
+    Ok(code)
+}
+
+/// Helper for `build.rs`: reads `<exh_dir>/<sheet>.exh` for each sheet, writes the
+/// generated structs to `$OUT_DIR/sheets.rs` and tells cargo to rerun when a header
+/// changes. Returns the path written, which the crate can `include!`.
+pub fn build_rs(exh_dir: &Path, sheets: &[&str], schemas: Option<&SchemaSet>) -> Result<PathBuf, FFXIVError> {
+    let out_dir = std::env::var_os("OUT_DIR")
+        .ok_or_else(|| FFXIVError::Custom(String::from("OUT_DIR is not set; call build_rs from a build script")))?;
+    let mut headers = Vec::with_capacity(sheets.len());
+    for sheet in sheets {
+        let path = exh_dir.join(format!("{}.exh", sheet));
+        println!("cargo:rerun-if-changed={}", path.display());
+        headers.push((String::from(*sheet), fs::read(&path)?));
+    }
+    let out = Path::new(&out_dir).join("sheets.rs");
+    fs::write(&out, generate_module(&headers, schemas)?)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod codegen_test {
+    use super::*;
+    use super::super::encoding::encode_sheet_info;
+    use super::super::ex::{BasicInfo, BitFlagsInfo, SheetLanguage, SheetPage, StringInfo};
+    use std::collections::HashSet;
+
+    #[test]
+    fn names() {
+        assert_eq!(field_name("LevelItem"), "level_item");
+        assert_eq!(field_name("BaseParam[2]"), "base_param_2");
+        assert_eq!(field_name("Item{Result}"), "item_result");
+        assert_eq!(field_name("Type"), "r#type");
+        assert_eq!(field_name("Yield"), "r#yield");
+        assert_eq!(field_name("Self"), "self_");
+        assert_eq!(field_name("2ndClass"), "column_2nd_class");
+        assert_eq!(struct_name("Item"), "Item");
+        assert_eq!(struct_name("quest/001/ClsHyu001_00194"), "Quest001ClsHyu00100194");
+    }
+
+    #[test]
+    fn struct_from_header() {
+        let mut languages = HashSet::new();
+        languages.insert(SheetLanguage::English);
+        let info = SheetInfo {
+            data_types: vec![
+                SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+                SheetDataType::UShort(BasicInfo { pointer: 4 }),
+                SheetDataType::BitFlags(BitFlagsInfo { pointer: 6, bit: 1 }),
+            ],
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }],
+            languages,
+            num_entries: 0,
+            data_set_size: 8,
+            variant: 1
+        };
+        let schemas = SchemaSet::from_saint_coinach_ex_json(r#"{ "sheets": [ { "sheet": "ItemAction", "definitions": [
+            { "name": "Name" }, { "index": 2, "name": "Name" } ] } ] }"#).unwrap();
+        let code = generate_module(&[(String::from("ItemAction"), encode_sheet_info(&info).unwrap())], Some(&schemas)).unwrap();
+        assert_eq!(code, "// Generated by sqpack_blue::sheet::codegen. Do not edit.
+
+/// A row of the `ItemAction` sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemAction {
+    /// `Name`, column 0
+    pub name: ::sqpack_blue::sheet::sestring::SeString,
+    /// Column 1
+    pub column_1: u16,
+    /// `Name`, column 2
+    pub name_2: bool,
+}
+
+impl ::sqpack_blue::sheet::FromSheetRow for ItemAction {
+    fn from_sheet_row(row: &::sqpack_blue::sheet::SheetRowRef) -> ::std::result::Result<Self, ::sqpack_blue::sheet::SheetError> {
+        ::std::result::Result::Ok(ItemAction {
+            name: row.get(0usize)?,
+            column_1: row.get(1usize)?,
+            name_2: row.get(2usize)?,
+        })
+    }
+}
+");
+    }
+
+    fn keyword_sheet() -> (SheetInfo, SchemaSet) {
+        let info = SheetInfo {
+            data_types: vec![
+                SheetDataType::UShort(BasicInfo { pointer: 0 }),
+                SheetDataType::Bool(BasicInfo { pointer: 2 }),
+                SheetDataType::String(StringInfo { pointer: 4, strings_offset: 12 }),
+                SheetDataType::UInt(BasicInfo { pointer: 8 }),
+            ],
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }],
+            languages: vec![SheetLanguage::English].into_iter().collect(),
+            num_entries: 0,
+            data_set_size: 12,
+            variant: 1
+        };
+        let schemas = SchemaSet::from_saint_coinach_ex_json(r#"{ "sheets": [ { "sheet": "Keywords", "definitions": [
+            { "name": "Type" }, { "index": 1, "name": "Self" }, { "index": 2, "name": "Gen" },
+            { "index": 3, "name": "Yield" } ] } ] }"#).unwrap();
+        (info, schemas)
+    }
+
+    mod generated {
+        include!("codegen_fixture.rs");
+    }
+
+    #[test]
+    fn generated_code_compiles() {
+        use super::super::encoding::RowBuilder;
+        use super::super::{FromSheetRow, SheetValue};
+        let (info, schemas) = keyword_sheet();
+        let code = generate_module(&[(String::from("Keywords"), encode_sheet_info(&info).unwrap())], Some(&schemas)).unwrap();
+        assert_eq!(code, include_str!("codegen_fixture.rs"));
+
+        let mut builder = RowBuilder::new(std::rc::Rc::new(info.data_types.clone()), info.data_set_size);
+        builder.set(0, &SheetValue::UShort(3)).unwrap();
+        builder.set(1, &SheetValue::Bool(true)).unwrap();
+        builder.set(2, &SheetValue::String("Potion".into())).unwrap();
+        builder.set(3, &SheetValue::UInt(7)).unwrap();
+        let row = generated::Keywords::from_sheet_row(&builder.build_row().as_row_ref()).unwrap();
+        assert_eq!((row.r#type, row.self_, row.r#gen.to_string(), row.r#yield), (3, true, String::from("Potion"), 7));
+    }
+}
@@ -0,0 +1,25 @@
+// Generated by sqpack_blue::sheet::codegen. Do not edit.
+
+/// A row of the `Keywords` sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keywords {
+    /// `Type`, column 0
+    pub r#type: u16,
+    /// `Self`, column 1
+    pub self_: bool,
+    /// `Gen`, column 2
+    pub r#gen: ::sqpack_blue::sheet::sestring::SeString,
+    /// `Yield`, column 3
+    pub r#yield: u32,
+}
+
+impl ::sqpack_blue::sheet::FromSheetRow for Keywords {
+    fn from_sheet_row(row: &::sqpack_blue::sheet::SheetRowRef) -> ::std::result::Result<Self, ::sqpack_blue::sheet::SheetError> {
+        ::std::result::Result::Ok(Keywords {
+            r#type: row.get(0usize)?,
+            self_: row.get(1usize)?,
+            r#gen: row.get(2usize)?,
+            r#yield: row.get(3usize)?,
+        })
+    }
+}
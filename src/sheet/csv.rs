@@ -0,0 +1,509 @@
+use super::{Sheet, SheetRow, SheetValue};
+use super::ex::{SheetDataType, SheetInfo};
+use super::schema::SheetSchema;
+use super::sestring::SeString;
+use super::encoding::{RowBuilder, encode_sheet_pages};
+use super::flags::{FlagGroup, flag_groups};
+use super::lazy::SheetRowIter;
+use ::FFXIVError;
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+/// When fields are wrapped in quotes. Fields containing the delimiter, a quote or a line
+/// break are always quoted, and quotes inside fields are doubled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuoteStyle {
+    Always,
+    Necessary,
+    /// Quote everything except integers, floats and booleans.
+    NonNumeric
+}
+
+/// What the header row holds for each column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderStyle {
+    None,
+    /// The type name of the column, e.g. `uint16`.
+    Types,
+    /// The index of the column.
+    Indices,
+    /// The schema name of the column, or its type name if it has none.
+    Names
+}
+
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub quoting: QuoteStyle,
+    pub header: HeaderStyle,
+    /// End lines with `\r\n` instead of `\n`.
+    pub crlf: bool,
+    /// Write the three header lines (`key`, `#`, types) and value formats used by
+    /// SaintCoinach's raw exports. Overrides `header`.
+    pub saint_coinach: bool,
+    /// Write each group of BitFlags columns that share a byte as one column holding the
+    /// byte, in place of the group's first column. Applies to `write_header` and
+    /// `write_row`, and to imports.
+    pub group_flags: bool
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        CsvOptions {
+            delimiter: ',',
+            quoting: QuoteStyle::Always,
+            header: HeaderStyle::Names,
+            crlf: false,
+            saint_coinach: false,
+            group_flags: false
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Options matching the CSV files written by SaintCoinach.
+    pub fn saint_coinach() -> CsvOptions {
+        CsvOptions {
+            delimiter: ',',
+            quoting: QuoteStyle::NonNumeric,
+            header: HeaderStyle::Names,
+            crlf: true,
+            saint_coinach: true,
+            group_flags: false
+        }
+    }
+}
+
+/// A column of the CSV file: a sheet column, or a group of BitFlags columns written as
+/// their byte.
+#[derive(Clone)]
+pub(super) enum CsvColumn {
+    Cell(usize),
+    Flags(FlagGroup)
+}
+
+/// The CSV columns of a sheet, in order. Without `group_flags` these are the sheet columns.
+pub(super) fn csv_columns(types: &[SheetDataType], schema: Option<&SheetSchema>, group_flags: bool) -> Vec<CsvColumn> {
+    if !group_flags {
+        return (0..types.len()).map(CsvColumn::Cell).collect();
+    }
+    let mut groups = flag_groups(types, schema);
+    let mut columns = Vec::with_capacity(types.len());
+    for (cell, data_type) in types.iter().enumerate() {
+        match data_type {
+            SheetDataType::BitFlags(_) => if let Some(position) = groups.iter().position(|g| g.first_column() == cell) {
+                columns.push(CsvColumn::Flags(groups.remove(position)));
+            },
+            _ => columns.push(CsvColumn::Cell(cell))
+        }
+    }
+    columns
+}
+
+impl CsvColumn {
+    fn index(&self) -> usize {
+        match self {
+            CsvColumn::Cell(cell) => *cell,
+            CsvColumn::Flags(group) => group.first_column()
+        }
+    }
+
+    /// The schema name of the column. Flag groups join the names of their bits with `|`,
+    /// using the type name for bits without one.
+    fn name(&self, types: &[SheetDataType], schema: Option<&SheetSchema>) -> Option<String> {
+        match self {
+            CsvColumn::Cell(cell) => schema.and_then(|s| s.column_name(*cell)).map(String::from),
+            CsvColumn::Flags(group) if group.bits.iter().any(|b| b.name.is_some()) => Some(group.bits.iter()
+                .map(|b| b.name.clone().unwrap_or_else(|| types[b.column].get_header()))
+                .collect::<Vec<String>>()
+                .join("|")),
+            CsvColumn::Flags(_) => None
+        }
+    }
+
+    fn header(&self, types: &[SheetDataType]) -> String {
+        match self {
+            CsvColumn::Cell(cell) => types[*cell].get_header(),
+            CsvColumn::Flags(_) => String::from("bitflags")
+        }
+    }
+
+    fn saint_coinach_header(&self, types: &[SheetDataType]) -> String {
+        match self {
+            CsvColumn::Cell(cell) => types[*cell].get_saint_coinach_header(),
+            CsvColumn::Flags(_) => String::from("byte")
+        }
+    }
+}
+
+/// Writes sheet rows as CSV, one row at a time.
+pub struct CsvWriter<'a> {
+    buffer: &'a mut dyn Write,
+    options: CsvOptions,
+    /// The grouped layout, once known, when `group_flags` is set.
+    grouped: Option<Vec<CsvColumn>>
+}
+
+impl<'a> CsvWriter<'a> {
+    pub fn new(buffer: &'a mut dyn Write, options: CsvOptions) -> CsvWriter<'a> {
+        CsvWriter { buffer, options, grouped: None }
+    }
+
+    pub fn write_header(&mut self, types: &[SheetDataType], schema: Option<&SheetSchema>) -> Result<(), FFXIVError> {
+        let columns = csv_columns(types, schema, self.options.group_flags);
+        if self.options.group_flags {
+            self.grouped = Some(columns.clone());
+        }
+        let columns: Vec<(CsvColumn, Option<String>)> = columns.into_iter().map(|c| (c, None)).collect();
+        self.write_header_columns(&columns, types, schema)
+    }
+
+    /// Writes the header lines for `columns` as `write_header` does. A column with a
+    /// suffix has `_<suffix>` appended to its index and name, e.g. to tell apart the
+    /// languages of a string column.
+    pub(super) fn write_header_columns(&mut self, columns: &[(CsvColumn, Option<String>)], types: &[SheetDataType], schema: Option<&SheetSchema>) -> Result<(), FFXIVError> {
+        let suffixed = |text: String, suffix: &Option<String>| match suffix {
+            Some(suffix) => format!("{}_{}", text, suffix),
+            None => text
+        };
+        if self.options.saint_coinach {
+            let indices: Vec<String> = columns.iter().map(|(c, s)| suffixed(c.index().to_string(), s)).collect();
+            self.write_line("key", &indices)?;
+            let names: Vec<String> = columns.iter().map(|(c, s)| c.name(types, schema).map(|n| suffixed(n, s)).unwrap_or_default()).collect();
+            self.write_line("#", &names)?;
+            let type_names: Vec<String> = columns.iter().map(|(c, _)| c.saint_coinach_header(types)).collect();
+            return self.write_line("int32", &type_names);
+        }
+        let headers: Vec<String> = match self.options.header {
+            HeaderStyle::None => return Ok(()),
+            HeaderStyle::Types => columns.iter().map(|(c, s)| suffixed(c.header(types), s)).collect(),
+            HeaderStyle::Indices => columns.iter().map(|(c, s)| suffixed(c.index().to_string(), s)).collect(),
+            HeaderStyle::Names => columns.iter()
+                .map(|(c, s)| suffixed(c.name(types, schema).unwrap_or_else(|| c.header(types)), s))
+                .collect()
+        };
+        self.write_line("index", &headers)
+    }
+
+    pub fn write_row(&mut self, index: usize, row: &SheetRow) -> Result<(), FFXIVError> {
+        let mut values: Vec<Option<SheetValue>> = row.values()?.into_iter().map(Some).collect();
+        if self.options.group_flags {
+            let columns = self.grouped.get_or_insert_with(|| csv_columns(&row.types, None, true));
+            values = columns.iter().map(|c| match c {
+                CsvColumn::Cell(cell) => values[*cell].take(),
+                CsvColumn::Flags(group) => row.by.get(group.pointer as usize).map(|b| SheetValue::UByte(*b))
+            }).collect();
+        }
+        self.write_values(index, &values)
+    }
+
+    /// Writes a row of values. Missing values are written as empty fields.
+    pub fn write_values(&mut self, index: usize, values: &[Option<SheetValue>]) -> Result<(), FFXIVError> {
+        self.write_field(&index.to_string(), true)?;
+        for value in values {
+            write!(self.buffer, "{}", self.options.delimiter)?;
+            let numeric = !matches!(value, Some(SheetValue::String(_)));
+            let text = match value {
+                Some(SheetValue::Bool(v)) | Some(SheetValue::BitFlags(v)) if self.options.saint_coinach =>
+                    String::from(if *v { "True" } else { "False" }),
+                Some(other) => other.to_string(),
+                None => String::new()
+            };
+            self.write_field(&text, numeric)?;
+        }
+        self.end_line()
+    }
+
+    /// Writes a header line. Header fields are only quoted with `QuoteStyle::Always`,
+    /// or when they contain special characters.
+    fn write_line(&mut self, first: &str, fields: &[String]) -> Result<(), FFXIVError> {
+        self.write_field(first, true)?;
+        for field in fields {
+            write!(self.buffer, "{}", self.options.delimiter)?;
+            self.write_field(field, true)?;
+        }
+        self.end_line()
+    }
+
+    fn end_line(&mut self) -> Result<(), FFXIVError> {
+        if self.options.crlf {
+            write!(self.buffer, "\r\n")?;
+        } else {
+            writeln!(self.buffer)?;
+        }
+        Ok(())
+    }
+
+    fn write_field(&mut self, field: &str, numeric: bool) -> Result<(), FFXIVError> {
+        let delimiter = self.options.delimiter;
+        let necessary = field.chars().any(|c| c == delimiter || c == '"' || c == '\r' || c == '\n');
+        let quote = necessary || match self.options.quoting {
+            QuoteStyle::Always => true,
+            QuoteStyle::Necessary => false,
+            QuoteStyle::NonNumeric => !numeric
+        };
+        if quote {
+            write!(self.buffer, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            write!(self.buffer, "{}", field)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a whole sheet as CSV with the given options.
+pub fn write_csv_with_options(sheet: &Sheet, buffer: &mut dyn Write, options: &CsvOptions) -> Result<(), FFXIVError> {
+    let mut writer = CsvWriter::new(buffer, options.clone());
+    writer.write_header(&sheet.types, sheet.schema.as_ref().map(|s| s.as_ref()))?;
+    for (index, row) in sheet.rows.iter() {
+        writer.write_row(*index, row)?;
+    }
+    Ok(())
+}
+
+/// Writes streamed rows as CSV with the given options, holding one page at a time.
+pub fn write_csv_stream(rows: SheetRowIter, buffer: &mut dyn Write, options: &CsvOptions) -> Result<(), FFXIVError> {
+    let mut writer = CsvWriter::new(buffer, options.clone());
+    writer.write_header(rows.types(), rows.schema())?;
+    for row in rows {
+        let (index, row) = row?;
+        writer.write_row(index, &row)?;
+    }
+    Ok(())
+}
+
+/// An error found while importing a CSV file. `line` is the 1-based line the record
+/// starts on, and `column` the 0-based sheet column of the offending cell.
+#[derive(Debug)]
+pub enum CsvImportError {
+    IO(std::io::Error),
+    /// A record that can't be split into fields, e.g. an unterminated quote.
+    Syntax { line: usize, message: String },
+    /// A record with the wrong number of fields, or a row id that isn't a number or
+    /// repeats an earlier record's.
+    Record { line: usize, message: String },
+    /// A cell that can't be parsed as the type of its column.
+    Cell { line: usize, column: usize, message: String },
+    /// The rows couldn't be laid out into pages, e.g. a row id outside every page.
+    Encoding(FFXIVError)
+}
+
+impl std::fmt::Display for CsvImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CsvImportError::IO(e) => write!(f, "IO error: {}", e),
+            CsvImportError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            CsvImportError::Record { line, message } => write!(f, "line {}: {}", line, message),
+            CsvImportError::Cell { line, column, message } => write!(f, "line {}, column {}: {}", line, column, message),
+            CsvImportError::Encoding(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for CsvImportError {}
+
+impl From<std::io::Error> for CsvImportError {
+    fn from(e: std::io::Error) -> CsvImportError {
+        CsvImportError::IO(e)
+    }
+}
+
+/// A record read from a CSV file, with the line it starts on.
+struct CsvRecord {
+    line: usize,
+    fields: Vec<String>
+}
+
+/// Splits CSV text into records. Quoted fields may contain delimiters, doubled quotes
+/// and line breaks; `\r\n` and `\n` both end a record.
+fn parse_records(text: &str, delimiter: char) -> Result<Vec<CsvRecord>, CsvImportError> {
+    let mut records = Vec::<CsvRecord>::new();
+    let mut fields = Vec::<String>::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut in_quotes = false;
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => in_quotes = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                in_quotes = true;
+                quoted = true;
+            },
+            '"' => return Err(CsvImportError::Syntax { line, message: String::from("unexpected quote in field") }),
+            c if c == delimiter => {
+                fields.push(std::mem::take(&mut field));
+                quoted = false;
+            },
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                records.push(CsvRecord { line: start_line, fields: std::mem::take(&mut fields) });
+                quoted = false;
+                line += 1;
+                start_line = line;
+            },
+            c if quoted => return Err(CsvImportError::Syntax {
+                line, message: format!("unexpected {:?} after closing quote", c)
+            }),
+            c => field.push(c)
+        }
+    }
+    if in_quotes {
+        return Err(CsvImportError::Syntax { line: start_line, message: String::from("unterminated quoted field") });
+    }
+    if quoted || !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push(CsvRecord { line: start_line, fields });
+    }
+    Ok(records)
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    match text.to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None
+    }
+}
+
+/// Parses a cell in the format written by `CsvWriter`.
+fn parse_cell(data_type: &SheetDataType, text: &str) -> Result<SheetValue, String> {
+    fn number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+        text.trim().parse::<T>().map_err(|_| format!("{:?} is not a valid number", text))
+    }
+    Ok(match data_type {
+        SheetDataType::String(_) => SheetValue::String(text.parse::<SeString>()
+            .map_err(|_| format!("{:?} has a malformed <hex:..> tag", text))?),
+        SheetDataType::Bool(_) => SheetValue::Bool(parse_bool(text)
+            .ok_or_else(|| format!("{:?} is not a boolean", text))?),
+        SheetDataType::BitFlags(_) => SheetValue::BitFlags(parse_bool(text)
+            .ok_or_else(|| format!("{:?} is not a boolean", text))?),
+        SheetDataType::Byte(_) => SheetValue::Byte(number(text)?),
+        SheetDataType::UByte(_) => SheetValue::UByte(number(text)?),
+        SheetDataType::Short(_) => SheetValue::Short(number(text)?),
+        SheetDataType::UShort(_) => SheetValue::UShort(number(text)?),
+        SheetDataType::Int(_) => SheetValue::Int(number(text)?),
+        SheetDataType::UInt(_) => SheetValue::UInt(number(text)?),
+        SheetDataType::Float(_) => SheetValue::Float(number(text)?),
+        SheetDataType::PackedInts(_) => SheetValue::PackedInts(number(text)?)
+    })
+}
+
+/// Checks the header lines of a CSV file against the column layout of the sheet. Names
+/// can't be checked without the schema they came from, so a header of names is only
+/// checked for its column count.
+fn check_header(header: &[CsvRecord], types: &[SheetDataType], columns: &[CsvColumn], options: &CsvOptions) -> Result<(), CsvImportError> {
+    let (record, first, expected): (&CsvRecord, &str, Option<Vec<String>>) = if options.saint_coinach {
+        match header.get(2) {
+            Some(record) => (record, "int32", Some(columns.iter().map(|c| c.saint_coinach_header(types)).collect())),
+            None => return Ok(())
+        }
+    } else {
+        match header.first() {
+            Some(record) => (record, "index", match options.header {
+                HeaderStyle::Types => Some(columns.iter().map(|c| c.header(types)).collect()),
+                HeaderStyle::Indices => Some(columns.iter().map(|c| c.index().to_string()).collect()),
+                _ => None
+            }),
+            None => return Ok(())
+        }
+    };
+    let mismatch = |message: String| Err(CsvImportError::Record { line: record.line, message });
+    if record.fields.len() != columns.len() + 1 {
+        return mismatch(format!("header has {} fields, but the sheet has {} columns", record.fields.len(), columns.len() + 1));
+    }
+    if record.fields[0] != first {
+        return mismatch(format!("header starts with {:?}, expected {:?}", record.fields[0], first));
+    }
+    if let Some(expected) = expected {
+        if let Some((found, wanted)) = record.fields[1..].iter().zip(&expected).find(|(f, e)| f != e) {
+            return mismatch(format!("header has column {:?} where the sheet has {:?}", found, wanted));
+        }
+    }
+    Ok(())
+}
+
+/// Reads a CSV file in the format written with `options` and builds the rows it holds,
+/// keyed by row id, using the column layout of `info`.
+pub fn read_csv_rows(input: &mut dyn Read, info: &SheetInfo, options: &CsvOptions)
+    -> Result<Vec<(usize, Vec<u8>)>, CsvImportError> {
+    let mut text = String::new();
+    input.read_to_string(&mut text)?;
+    let header_lines = if options.saint_coinach {
+        3
+    } else if options.header == HeaderStyle::None {
+        0
+    } else {
+        1
+    };
+    let types = Rc::new(info.data_types.clone());
+    let columns = csv_columns(&types, None, options.group_flags);
+    let records = parse_records(&text, options.delimiter)?;
+    check_header(&records[..header_lines.min(records.len())], &types, &columns, options)?;
+    let mut rows = Vec::<(usize, Vec<u8>)>::new();
+    let mut ids = HashSet::<usize>::new();
+    for record in records.into_iter().skip(header_lines) {
+        if record.fields.len() != columns.len() + 1 {
+            return Err(CsvImportError::Record {
+                line: record.line,
+                message: format!("expected {} fields, found {}", columns.len() + 1, record.fields.len())
+            });
+        }
+        let id = record.fields[0].trim().parse::<usize>().map_err(|_| CsvImportError::Record {
+            line: record.line,
+            message: format!("{:?} is not a valid row id", record.fields[0])
+        })?;
+        if !ids.insert(id) {
+            return Err(CsvImportError::Record { line: record.line, message: format!("row {} appears more than once", id) });
+        }
+        let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+        for (csv_column, text) in columns.iter().zip(&record.fields[1..]) {
+            let column = csv_column.index();
+            let values = match csv_column {
+                CsvColumn::Cell(cell) => vec![(*cell, parse_cell(&types[*cell], text))],
+                CsvColumn::Flags(group) => match text.trim().parse::<u8>() {
+                    Ok(byte) => group.bits.iter()
+                        .map(|b| (b.column, Ok(SheetValue::BitFlags(byte & 1 << b.bit != 0))))
+                        .collect(),
+                    Err(_) => vec![(column, Err(format!("{:?} is not a valid flag byte", text)))]
+                }
+            };
+            for (cell, value) in values {
+                let value = value.map_err(|message| CsvImportError::Cell { line: record.line, column, message })?;
+                builder.set(cell, &value).map_err(|e| CsvImportError::Cell {
+                    line: record.line, column, message: e.to_string()
+                })?;
+            }
+        }
+        rows.push((id, builder.build()));
+    }
+    Ok(rows)
+}
+
+/// Reads a CSV file in the format written with `options` and encodes it into EXDF
+/// pages, in the order of `info.pages`.
+pub fn import_csv(input: &mut dyn Read, info: &SheetInfo, options: &CsvOptions) -> Result<Vec<Vec<u8>>, CsvImportError> {
+    let rows = read_csv_rows(input, info, options)?;
+    encode_sheet_pages(info, rows.iter().map(|(id, by)| (*id, by.as_slice())))
+        .map_err(CsvImportError::Encoding)
+}
@@ -1,5 +1,5 @@
 use super::ex::*;
-use super::{Sheet, SheetRow};
+use super::{Sheet, SheetRow, SheetRowRef};
 use byteorder::{LittleEndian, BigEndian};
 use byteorder::ByteOrder;
 use ::FFXIVError;
@@ -9,8 +9,9 @@ use std::collections::HashSet;
 
 /// A magic u32 present at the start of every EXHF File
 /// Encodes 'EXHF' in big-endian ASCII
-const EXHF_MAGIC: u32 = 0x45584846;
-const EXDF_MAGIC: u32 = 0x45584446;
+pub const EXHF_MAGIC: u32 = 0x45584846;
+/// Encodes 'EXDF' in big-endian ASCII
+pub const EXDF_MAGIC: u32 = 0x45584446;
 
 
 /// Decodes a Vec<u8> of the EXHF into a SheetInfo struct
@@ -27,6 +28,7 @@ pub fn decode_sheet_info(exh: &Vec<u8>) -> Result<SheetInfo, FFXIVError> {
     let num_types: u16 = BigEndian::read_u16(&exh[0x8..0xa]);
     let num_pages: u16 = BigEndian::read_u16(&exh[0xa..0xc]);
     let num_langs: u16 = BigEndian::read_u16(&exh[0xc..0xe]);
+    let variant: u8 = exh[0x11];
     let num_entries: u32 = BigEndian::read_u32(&exh[0x14..0x18]);
 
     let required_length = 0x20 + (4 * num_types) + (8 * num_pages) + (2 * num_langs);
@@ -53,7 +55,7 @@ pub fn decode_sheet_info(exh: &Vec<u8>) -> Result<SheetInfo, FFXIVError> {
     let languages = decode_lang_table(&exh[lang_table_start..lang_table_end], &num_langs)?;
 
     Ok(SheetInfo{
-        data_types, languages, pages, num_entries
+        data_types, languages, pages, num_entries, data_set_size, variant
     })
 
 }
@@ -111,17 +113,10 @@ fn decode_lang_table(exh_lang_table: &[u8], num_langs: &u16) -> Result<HashSet<S
     for i in 0..*num_langs as usize {
         let lang_code = LittleEndian::read_u16(&exh_lang_table[i * 2 .. i * 2 + 2]);
         langs.insert(
-            match lang_code {
-                0x0 => SheetLanguage::None,
-                0x1 => SheetLanguage::Japanese,
-                0x2 => SheetLanguage::English,
-                0x3 => SheetLanguage::German,
-                0x4 => SheetLanguage::French,
-                0x5 => SheetLanguage::ChineseS,
-                0x6 => SheetLanguage::ChineseT,
-                0x7 => SheetLanguage::Korean,
-                unknown => return Err(FFXIVError::DecodingEXD(
-                    Box::new(FFXIVError::Custom(format!("Unknown language code: {}", unknown)))
+            match SheetLanguage::from_language_id(lang_code) {
+                Some(lang) => lang,
+                None => return Err(FFXIVError::DecodingEXD(
+                    Box::new(FFXIVError::Custom(format!("Unknown language code: {}", lang_code)))
                 ))
             }
         );
@@ -137,6 +132,17 @@ fn decode_lang_table(exh_lang_table: &[u8], num_langs: &u16) -> Result<HashSet<S
 //    println!("]}}");
 //}
 
+/// Reads every page of a sheet with `read_page`, which gets the bytes of a page's EXDF
+/// file, and decodes them.
+pub fn read_sheet_pages<F>(info: &SheetInfo, mut read_page: F) -> Result<Sheet, FFXIVError>
+    where F: FnMut(&SheetPage) -> Result<Vec<u8>, FFXIVError> {
+    let mut all_page_data = Vec::<Vec<u8>>::with_capacity(info.pages.len());
+    for page in &info.pages {
+        all_page_data.push(read_page(page)?);
+    }
+    decode_sheet_from_bytes(info, &all_page_data)
+}
+
 /// Decodes a sheet from bytes given the header info and all pages of the data file.
 pub fn decode_sheet_from_bytes(exh: &SheetInfo, exd: &Vec<Vec<u8>>) -> Result<Sheet, FFXIVError> {
 
@@ -144,93 +150,139 @@ pub fn decode_sheet_from_bytes(exh: &SheetInfo, exd: &Vec<Vec<u8>>) -> Result<Sh
     let mut sheet = Sheet {
         rows: indexmap::IndexMap::new(),
         types: types.clone(),
-        column_count: exh.data_types.len() as u32
+        column_count: exh.data_types.len() as u32,
+        schema: None
     };
 
-    let mut page_index: usize = 0;
-    for page in &exh.pages {
-        let pexd: &Vec<u8> = exd.get(page_index).unwrap();
-        if pexd.len() < 0x20 {
-            return Err(FFXIVError::DecodingEXD(
-                Box::new(FFXIVError::Custom(format!("Malformed data in EXDF - length < 0x20")))
-            ));
-        };
-
-        let magic: u32 = BigEndian::read_u32(&pexd[0..4]);
-        if magic != EXDF_MAGIC { return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::MagicMissing))) };
-
+    for (page_index, page) in exh.pages.iter().enumerate() {
+        let pexd = exd.get(page_index).ok_or_else(|| FFXIVError::DecodingEXD(
+            Box::new(FFXIVError::Custom(format!("Missing data for EXDF page {}", page.page_entry)))
+        ))?;
+        for (row_index, row) in decode_sheet_page(page, pexd, &types, exh.data_set_size)? {
+            if sheet.rows.contains_key(&row_index) {
+                return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(String::from("Duplicate rows in EXDF")))));
+            }
+            sheet.rows.insert(row_index, row);
+        }
+    }
 
+    Ok(sheet)
+}
 
-        let offset_size: u32 = BigEndian::read_u32(&pexd[0x8..0xc]);
-        let data_size: u32 = BigEndian::read_u32(&pexd[0xc..0x10]);
-        let required_size = 0x20 as usize + offset_size as usize + data_size as usize;
-        if pexd.len() < required_size {
-            return Err(
-                FFXIVError::DecodingEXD(Box::new(
-                    FFXIVError::Custom(format!("Malformed data in EXDF. Actual size < Required Size, {} < {}",
-                        pexd.len(),
-                        required_size
-                    ))
-                ))
-            )
-        }
+/// Decodes the rows of a single EXDF page, keyed by row id. Rows smaller than
+/// `data_set_size`, the fixed-length data size from the EXH, are an error.
+pub fn decode_sheet_page(page: &SheetPage, pexd: &[u8], types: &Rc<Vec<SheetDataType>>, data_set_size: u16) -> Result<indexmap::IndexMap<usize, SheetRow>, FFXIVError> {
+    Ok(decode_sheet_page_ref(page, pexd, types, data_set_size)?.into_iter()
+        .map(|(row_index, row)| (row_index, row.to_sheet_row(types)))
+        .collect())
+}
 
-        let offset_start: usize = 0x20;
-
-        let mut exd_table= indexmap::IndexMap::<usize, u32>::with_capacity(page.page_size as usize);
-        {
-            let mut current_index: usize = 0;
-            let mut last_row: Option<usize> = None;
-            while last_row.map(|lr| lr < page.page_entry as usize + page.page_size as usize).unwrap_or(true) {
-                let r_ind_start = offset_start + 8 * current_index;
-                if r_ind_start >= offset_start + offset_size as usize {
-                    break;
-                }
-                let r_ind_end = r_ind_start + 4;
-                let r_off_start = r_ind_end.clone();
-                let r_off_end = r_off_start + 4;
-                let row_index: u32 = BigEndian::read_u32(&pexd[r_ind_start..r_ind_end]);
-                let row_offset: u32 = BigEndian::read_u32(&pexd[r_off_start..r_off_end]);
-
-                if exd_table.contains_key(&(row_index as usize)) {
-                    return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(format!("Duplicate rows in EXDF")))));
-                }
-
-                exd_table.insert(row_index as usize, row_offset);
-                last_row = Some(row_index as usize);
-                current_index += 1;
+/// Decodes every page of a sheet into rows borrowed from the page buffers. Unlike
+/// `decode_sheet_from_bytes`, row data isn't copied.
+pub fn decode_sheet_ref<'a>(exh: &'a SheetInfo, exd: &'a [Vec<u8>]) -> Result<indexmap::IndexMap<usize, SheetRowRef<'a>>, FFXIVError> {
+    let mut rows = indexmap::IndexMap::<usize, SheetRowRef<'a>>::with_capacity(exh.num_entries as usize);
+    for (page_index, page) in exh.pages.iter().enumerate() {
+        let pexd = exd.get(page_index).ok_or_else(|| FFXIVError::DecodingEXD(
+            Box::new(FFXIVError::Custom(format!("Missing data for EXDF page {}", page.page_entry)))
+        ))?;
+        for (row_index, row) in decode_sheet_page_ref(page, pexd, &exh.data_types, exh.data_set_size)? {
+            if rows.insert(row_index, row).is_some() {
+                return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(String::from("Duplicate rows in EXDF")))));
             }
         }
+    }
+    Ok(rows)
+}
 
-        for (row_index, row_offset) in exd_table {
-            if sheet.rows.contains_key(&(row_index as usize)) {
-                return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(format!("Duplicate rows in EXDF")))));
-            }
+/// Decodes the rows of a single EXDF page into rows borrowed from the page buffer. Rows
+/// smaller than `data_set_size` are an error.
+pub fn decode_sheet_page_ref<'a>(page: &SheetPage, pexd: &'a [u8], types: &'a [SheetDataType], data_set_size: u16) -> Result<Vec<(usize, SheetRowRef<'a>)>, FFXIVError> {
+    if pexd.len() < 0x20 {
+        return Err(FFXIVError::DecodingEXD(
+            Box::new(FFXIVError::Custom(String::from("Malformed data in EXDF - length < 0x20")))
+        ));
+    };
 
-            let row_size: u32 = BigEndian::read_u32(&pexd[row_offset as usize .. row_offset as usize + 4]);
-            let row_slicer = row_offset as usize + 6;
-            let row_slicer_end = row_slicer + row_size as usize;
-            if row_slicer_end > pexd.len() {
-                return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(format!("Malformed Data")))));
+    let magic: u32 = BigEndian::read_u32(&pexd[0..4]);
+    if magic != EXDF_MAGIC { return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::MagicMissing))) };
+
+    let offset_size: u32 = BigEndian::read_u32(&pexd[0x8..0xc]);
+    let data_size: u32 = BigEndian::read_u32(&pexd[0xc..0x10]);
+    let required_size = 0x20 + offset_size as usize + data_size as usize;
+    if pexd.len() < required_size {
+        return Err(
+            FFXIVError::DecodingEXD(Box::new(
+                FFXIVError::Custom(format!("Malformed data in EXDF. Actual size < Required Size, {} < {}",
+                    pexd.len(),
+                    required_size
+                ))
+            ))
+        )
+    }
+
+    if !offset_size.is_multiple_of(8) {
+        return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(
+            format!("Malformed data in EXDF. Offset table size is not a multiple of 8: {}", offset_size)))));
+    }
+    let offset_start: usize = 0x20;
+    let data_start = offset_start + offset_size as usize;
+
+    let mut exd_table= indexmap::IndexMap::<usize, u32>::with_capacity(page.page_size as usize);
+    {
+        let mut current_index: usize = 0;
+        let mut last_row: Option<usize> = None;
+        while last_row.map(|lr| lr < page.page_entry as usize + page.page_size as usize).unwrap_or(true) {
+            let r_ind_start = offset_start + 8 * current_index;
+            if r_ind_start >= data_start {
+                break;
+            }
+            let r_ind_end = r_ind_start + 4;
+            let r_off_start = r_ind_end;
+            let r_off_end = r_off_start + 4;
+            let row_index: u32 = BigEndian::read_u32(&pexd[r_ind_start..r_ind_end]);
+            let row_offset: u32 = BigEndian::read_u32(&pexd[r_off_start..r_off_end]);
+
+            if exd_table.contains_key(&(row_index as usize)) {
+                return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(String::from("Duplicate rows in EXDF")))));
             }
-            let row_slice: &[u8] = &pexd[row_slicer .. row_slicer_end];
 
-            sheet.rows.insert(row_index as usize, SheetRow {
-                types: types.clone(),
-                by: row_slice.to_vec()
-            });
+            exd_table.insert(row_index as usize, row_offset);
+            last_row = Some(row_index as usize);
+            current_index += 1;
         }
+    }
 
-//        for i in 0..page.page_size as usize {
-//
-//
-//
-//        }
+    let mut rows = Vec::<(usize, SheetRowRef<'a>)>::with_capacity(exd_table.len());
+    for (row_index, row_offset) in exd_table {
+        if (row_offset as usize) < data_start {
+            return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(
+                format!("Offset of row {} is inside the EXDF header or offset table: {} < {}", row_index, row_offset, data_start)))));
+        }
+        let row_slicer = row_offset as usize + 6;
+        if row_slicer > pexd.len() {
+            return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(
+                format!("Offset of row {} is past the end of the EXDF: {} > {}", row_index, row_offset, pexd.len())))));
+        }
+        let row_size: u32 = BigEndian::read_u32(&pexd[row_offset as usize .. row_offset as usize + 4]);
+        let row_slicer_end = row_slicer + row_size as usize;
+        if row_slicer_end > pexd.len() {
+            return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(
+                format!("Row {} runs past the end of the EXDF: {} > {}", row_index, row_slicer_end, pexd.len())))));
+        }
+        if row_size < data_set_size as u32 {
+            return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(
+                format!("Row {} is shorter than the row data size: {} < {}", row_index, row_size, data_set_size)))));
+        }
+        let row_slice: &[u8] = &pexd[row_slicer .. row_slicer_end];
 
-        page_index = page_index + 1;
+        rows.push((row_index, SheetRowRef {
+            by: row_slice,
+            types,
+            schema: None
+        }));
     }
 
-    Ok(sheet)
+    Ok(rows)
 }
 
 #[cfg(test)]
@@ -277,4 +329,48 @@ mod decode_test {
         let title: String = sr.read_cell_data(0).unwrap();
         assert_eq!("music/ffxiv/BGM_Field_Gri_01.scd", title);
     }
+
+    #[test]
+    fn short_rows_are_rejected() {
+        use super::super::encoding::encode_sheet_pages;
+        use super::super::ex::{BasicInfo, SheetLanguage};
+        let info = SheetInfo {
+            data_types: vec![SheetDataType::UInt(BasicInfo { pointer: 0 }), SheetDataType::UInt(BasicInfo { pointer: 4 })],
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }],
+            languages: vec![SheetLanguage::None].into_iter().collect(),
+            num_entries: 2,
+            data_set_size: 8,
+            variant: 1
+        };
+        let full = [0u8, 0, 0, 1, 0, 0, 0, 2];
+        let pages = encode_sheet_pages(&info, vec![(1, &full[..]), (2, &full[..2])]).unwrap();
+        match decode_sheet_page_ref(&info.pages[0], &pages[0], &info.data_types, info.data_set_size) {
+            Err(FFXIVError::DecodingEXD(e)) => assert!(e.to_string().contains("Row 2 is shorter")),
+            other => panic!("expected a decoding error, got {:?}", other.map(|rows| rows.len()))
+        }
+        assert!(decode_sheet_from_bytes(&info, &pages).is_err());
+        let pages = encode_sheet_pages(&info, vec![(1, &full[..])]).unwrap();
+        assert_eq!(decode_sheet_from_bytes(&info, &pages).unwrap().rows[&1].get::<u32>(1).unwrap(), 2);
+    }
+
+    #[test]
+    fn malformed_offset_tables_are_rejected() {
+        let page = SheetPage { page_entry: 0, page_size: 10 };
+        let types = [SheetDataType::UInt(super::super::ex::BasicInfo { pointer: 0 })];
+        let decode = |offset_size: u32, data: &[u8]| {
+            let mut exd = vec![0u8; 0x20];
+            BigEndian::write_u32(&mut exd[0..4], EXDF_MAGIC);
+            BigEndian::write_u32(&mut exd[0x8..0xc], offset_size);
+            exd.extend_from_slice(data);
+            match decode_sheet_page_ref(&page, &exd, &types, 4) {
+                Err(FFXIVError::DecodingEXD(e)) => e.to_string(),
+                other => panic!("expected a decoding error, got {:?}", other.map(|rows| rows.len()))
+            }
+        };
+        // An offset table cut off mid-entry
+        assert!(decode(4, &[0, 0, 0, 1]).contains("not a multiple of 8"));
+        // Row offsets pointing back into the header or the table itself
+        assert!(decode(8, &[0, 0, 0, 1, 0, 0, 0, 0]).contains("Offset of row 1 is inside"));
+        assert!(decode(8, &[0, 0, 0, 1, 0, 0, 0, 0x20]).contains("Offset of row 1 is inside"));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,257 @@
+use super::{Sheet, SheetError, SheetValue};
+use super::ex::SheetDataType;
+
+use std::fmt;
+
+use serde_json::{Value, json};
+
+/// A change to the column layout of a sheet, comparing columns by index.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutChange {
+    Added { column: usize, data_type: SheetDataType },
+    Removed { column: usize, data_type: SheetDataType },
+    /// The column changed type or moved to a different offset.
+    Changed { column: usize, old: SheetDataType, new: SheetDataType }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellChange {
+    pub column: usize,
+    /// The schema name of the column in the newer sheet, if it has one.
+    pub name: Option<String>,
+    pub old: SheetValue,
+    pub new: SheetValue
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowChange {
+    pub id: usize,
+    pub cells: Vec<CellChange>
+}
+
+/// The differences between two versions of a sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SheetDiff {
+    pub sheet: String,
+    pub layout: Vec<LayoutChange>,
+    pub added: Vec<usize>,
+    pub removed: Vec<usize>,
+    pub changed: Vec<RowChange>
+}
+
+/// The differences between the sheets of two installations.
+#[derive(Debug, Default)]
+pub struct DiffReport {
+    pub added_sheets: Vec<String>,
+    pub removed_sheets: Vec<String>,
+    /// Sheets that differ. Unchanged sheets are left out.
+    pub sheets: Vec<SheetDiff>,
+    /// Sheets that couldn't be read in one of the installations, with the error.
+    pub failed: Vec<(String, String)>
+}
+
+/// Describes a column type with its position, e.g. `uint16@4` or `bitflags[3]@6`.
+fn describe(data_type: &SheetDataType) -> String {
+    format!("{}@{}", data_type.get_header(), data_type.get_pointer())
+}
+
+/// Compares two versions of a sheet. Cells are only compared in columns whose type is
+/// the same in both, as other columns can't be read consistently.
+pub fn diff_sheets(name: &str, old: &Sheet, new: &Sheet) -> Result<SheetDiff, SheetError> {
+    let mut layout = Vec::new();
+    for column in 0..old.types.len().max(new.types.len()) {
+        match (old.types.get(column), new.types.get(column)) {
+            (Some(o), Some(n)) if o != n => layout.push(LayoutChange::Changed { column, old: *o, new: *n }),
+            (Some(o), None) => layout.push(LayoutChange::Removed { column, data_type: *o }),
+            (None, Some(n)) => layout.push(LayoutChange::Added { column, data_type: *n }),
+            _ => ()
+        }
+    }
+    let comparable: Vec<usize> = (0..old.types.len().min(new.types.len()))
+        .filter(|c| old.types[*c].get_header() == new.types[*c].get_header())
+        .collect();
+
+    let removed = old.rows.keys().filter(|id| !new.rows.contains_key(*id)).cloned().collect();
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for (id, new_row) in new.rows.iter() {
+        let old_row = match old.rows.get(id) {
+            Some(row) => row,
+            None => {
+                added.push(*id);
+                continue;
+            }
+        };
+        if old_row.by == new_row.by && old.types == new.types {
+            continue;
+        }
+        let mut cells = Vec::new();
+        for column in &comparable {
+            let (o, n) = (old_row.get::<SheetValue>(*column)?, new_row.get::<SheetValue>(*column)?);
+            if o != n {
+                cells.push(CellChange { column: *column, name: new.column_name(*column).map(String::from), old: o, new: n });
+            }
+        }
+        if !cells.is_empty() {
+            changed.push(RowChange { id: *id, cells });
+        }
+    }
+    Ok(SheetDiff { sheet: String::from(name), layout, added, removed, changed })
+}
+
+impl SheetDiff {
+    pub fn is_empty(&self) -> bool {
+        self.layout.is_empty() && self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "sheet": self.sheet,
+            "layout": self.layout.iter().map(|change| match change {
+                LayoutChange::Added { column, data_type } => json!({ "column": column, "added": describe(data_type) }),
+                LayoutChange::Removed { column, data_type } => json!({ "column": column, "removed": describe(data_type) }),
+                LayoutChange::Changed { column, old, new } => json!({ "column": column, "old": describe(old), "new": describe(new) })
+            }).collect::<Vec<_>>(),
+            "added": self.added,
+            "removed": self.removed,
+            "changed": self.changed.iter().map(|row| json!({
+                "id": row.id,
+                "cells": row.cells.iter().map(|cell| json!({
+                    "column": cell.column,
+                    "name": cell.name,
+                    "old": cell.old.to_json(),
+                    "new": cell.new.to_json()
+                })).collect::<Vec<_>>()
+            })).collect::<Vec<_>>()
+        })
+    }
+}
+
+impl DiffReport {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "added_sheets": self.added_sheets,
+            "removed_sheets": self.removed_sheets,
+            "sheets": self.sheets.iter().map(SheetDiff::to_json).collect::<Vec<_>>(),
+            "failed": self.failed.iter().map(|(sheet, error)| json!({ "sheet": sheet, "error": error })).collect::<Vec<_>>()
+        })
+    }
+}
+
+/// Renders a cell for the text report, quoting strings.
+fn render(value: &SheetValue) -> String {
+    match value {
+        SheetValue::String(s) => format!("{:?}", s.to_string()),
+        other => other.to_string()
+    }
+}
+
+impl fmt::Display for SheetDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}: {} added, {} removed, {} changed", self.sheet, self.added.len(), self.removed.len(), self.changed.len())?;
+        for change in &self.layout {
+            match change {
+                LayoutChange::Added { column, data_type } => writeln!(f, "  column {} added: {}", column, describe(data_type))?,
+                LayoutChange::Removed { column, data_type } => writeln!(f, "  column {} removed: {}", column, describe(data_type))?,
+                LayoutChange::Changed { column, old, new } =>
+                    writeln!(f, "  column {} changed: {} -> {}", column, describe(old), describe(new))?
+            }
+        }
+        for id in &self.added {
+            writeln!(f, "  + {}", id)?;
+        }
+        for id in &self.removed {
+            writeln!(f, "  - {}", id)?;
+        }
+        for row in &self.changed {
+            for cell in &row.cells {
+                let column = match &cell.name {
+                    Some(name) => name.clone(),
+                    None => cell.column.to_string()
+                };
+                writeln!(f, "  ~ {} [{}]: {} -> {}", row.id, column, render(&cell.old), render(&cell.new))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for sheet in &self.added_sheets {
+            writeln!(f, "+ sheet {}", sheet)?;
+        }
+        for sheet in &self.removed_sheets {
+            writeln!(f, "- sheet {}", sheet)?;
+        }
+        for (sheet, error) in &self.failed {
+            writeln!(f, "! sheet {}: {}", sheet, error)?;
+        }
+        for diff in &self.sheets {
+            write!(f, "{}", diff)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod diff_test {
+    use super::*;
+    use super::super::test_sheet;
+    use super::super::ex::{BasicInfo, StringInfo};
+    use super::super::schema::SheetSchema;
+
+    /// A sheet whose rows set the first two columns, a string and a 16-bit number.
+    fn sheet(types: Vec<SheetDataType>, rows: &[(usize, &str, u16)]) -> Sheet {
+        let level = |level: u16| match types[1] {
+            SheetDataType::Short(_) => SheetValue::Short(level as i16),
+            _ => SheetValue::UShort(level)
+        };
+        let rows: Vec<(usize, Vec<SheetValue>)> = rows.iter()
+            .map(|(id, name, l)| (*id, vec![SheetValue::String((*name).into()), level(*l)]))
+            .collect();
+        test_sheet(types, rows)
+    }
+
+    fn columns() -> Vec<SheetDataType> {
+        vec![SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }), SheetDataType::UShort(BasicInfo { pointer: 4 })]
+    }
+
+    #[test]
+    fn rows_and_cells() {
+        let old = sheet(columns(), &[(1, "Potion", 10), (2, "Ether", 20), (3, "Elixir", 30)]);
+        let mut new = sheet(columns(), &[(1, "Potion", 10), (3, "Elixir", 35), (4, "Megalixir", 40)]);
+        new.set_schema(&SheetSchema::from_saint_coinach_json(
+            r#"{ "sheet": "Item", "definitions": [ { "name": "Name" }, { "index": 1, "name": "Level" } ] }"#).unwrap());
+
+        let diff = diff_sheets("Item", &old, &new).unwrap();
+        assert_eq!(diff.added, vec![4]);
+        assert_eq!(diff.removed, vec![2]);
+        assert_eq!(diff.changed, vec![RowChange { id: 3, cells: vec![CellChange {
+            column: 1, name: Some(String::from("Level")), old: SheetValue::UShort(30), new: SheetValue::UShort(35)
+        }] }]);
+        assert!(diff.layout.is_empty());
+        assert_eq!(diff.to_string(), "Item: 1 added, 1 removed, 1 changed\n  + 4\n  - 2\n  ~ 3 [Level]: 30 -> 35\n");
+        assert_eq!(diff.to_json()["changed"][0]["cells"][0]["new"], json!(35));
+        assert!(diff_sheets("Item", &old, &old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn layout_changes() {
+        let old = sheet(columns(), &[(1, "Potion", 10)]);
+        let mut widened = columns();
+        widened[1] = SheetDataType::Short(BasicInfo { pointer: 4 });
+        widened.push(SheetDataType::Bool(BasicInfo { pointer: 6 }));
+        let new = sheet(widened, &[(1, "Hi-Potion", 10)]);
+
+        let diff = diff_sheets("Item", &old, &new).unwrap();
+        assert_eq!(diff.layout, vec![
+            LayoutChange::Changed { column: 1, old: SheetDataType::UShort(BasicInfo { pointer: 4 }), new: SheetDataType::Short(BasicInfo { pointer: 4 }) },
+            LayoutChange::Added { column: 2, data_type: SheetDataType::Bool(BasicInfo { pointer: 6 }) },
+        ]);
+        // Only the string column has the same type in both, so only it is compared
+        assert_eq!(diff.changed[0].cells.len(), 1);
+        assert_eq!(diff.to_string(), "Item: 0 added, 0 removed, 1 changed\n  column 1 changed: uint16@4 -> int16@4\n  \
+            column 2 added: bool@6\n  ~ 1 [0]: \"Potion\" -> \"Hi-Potion\"\n");
+    }
+}
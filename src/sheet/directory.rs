@@ -0,0 +1,89 @@
+use super::Sheet;
+use super::decoding::{decode_sheet_info, read_sheet_pages};
+use super::ex::{exd_file_name, SheetInfo, SheetLanguage};
+use ::FFXIVError;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Reads a file, naming it in the error if that fails.
+fn read_file(path: &Path) -> Result<Vec<u8>, FFXIVError> {
+    fs::read(path).map_err(|e| FFXIVError::IO(io::Error::new(e.kind(), format!("{}: {}", path.display(), e))))
+}
+
+/// Reads the EXHF header of a sheet from `<path>/<name>.exh`.
+pub fn read_directory_info(path: &Path, name: &str) -> Result<SheetInfo, FFXIVError> {
+    decode_sheet_info(&read_file(&path.join(format!("{}.exh", name)))?)
+}
+
+impl Sheet {
+    /// Reads a sheet from loose files, as laid out by extracted dumps and mod tools:
+    /// `<path>/<name>.exh` and a `<path>/<name>_<page>_<language>.exd` file per page.
+    /// `path` is usually the `exd` folder of the dump, and `name` may include subfolders,
+    /// e.g. `quest/001/ClsHyu001_00194`.
+    pub fn from_directory(path: &Path, name: &str, language: SheetLanguage) -> Result<Sheet, FFXIVError> {
+        let info = read_directory_info(path, name)?;
+        info.check_language(language)?;
+        read_sheet_pages(&info, |page| read_file(&path.join(exd_file_name(name, page, language))))
+    }
+
+    /// Reads a sheet from loose files in the first of `preferences` it has, like
+    /// `FFXIV::get_sheet_with_fallback`.
+    pub fn from_directory_with_fallback(path: &Path, name: &str, preferences: &[SheetLanguage]) -> Result<(Sheet, SheetLanguage), FFXIVError> {
+        let info = read_directory_info(path, name)?;
+        let language = info.preferred_language(preferences)?;
+        let sheet = read_sheet_pages(&info, |page| read_file(&path.join(exd_file_name(name, page, language))))?;
+        Ok((sheet, language))
+    }
+}
+
+#[cfg(test)]
+mod directory_test {
+    use super::*;
+    use super::super::SheetValue;
+    use super::super::encoding::{encode_sheet_info, encode_sheet_pages, RowBuilder};
+    use super::super::ex::{BasicInfo, SheetDataType, SheetPage};
+    use std::rc::Rc;
+
+    #[test]
+    fn loose_files() {
+        let info = SheetInfo {
+            data_types: vec![SheetDataType::UInt(BasicInfo { pointer: 0 })],
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }, SheetPage { page_entry: 10, page_size: 10 }],
+            languages: vec![SheetLanguage::English, SheetLanguage::German].into_iter().collect(),
+            num_entries: 3,
+            data_set_size: 4,
+            variant: 1
+        };
+        let types = Rc::new(info.data_types.clone());
+        let rows: Vec<(usize, Vec<u8>)> = [2usize, 11, 15].iter().map(|id| {
+            let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+            builder.set(0, &SheetValue::UInt(*id as u32 * 10)).unwrap();
+            (*id, builder.build())
+        }).collect();
+
+        let dir = std::env::temp_dir().join(format!("sqpack_blue_directory_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("quest")).unwrap();
+        fs::write(dir.join("quest/Test.exh"), encode_sheet_info(&info).unwrap()).unwrap();
+        for (page, data) in info.pages.iter().zip(encode_sheet_pages(&info, rows.iter().map(|(id, by)| (*id, by.as_slice()))).unwrap()) {
+            fs::write(dir.join(exd_file_name("quest/Test", page, SheetLanguage::German)), data).unwrap();
+        }
+
+        let sheet = Sheet::from_directory(&dir, "quest/Test", SheetLanguage::German).unwrap();
+        assert_eq!(sheet.rows.keys().cloned().collect::<Vec<_>>(), vec![2, 11, 15]);
+        assert_eq!(sheet.rows[&11].get::<u32>(0).unwrap(), 110);
+
+        let (_, language) = Sheet::from_directory_with_fallback(&dir, "quest/Test", &[SheetLanguage::French, SheetLanguage::German]).unwrap();
+        assert_eq!(language, SheetLanguage::German);
+        match Sheet::from_directory(&dir, "quest/Test", SheetLanguage::Japanese) {
+            Err(FFXIVError::InvalidLanguage(SheetLanguage::Japanese, _)) => (),
+            other => panic!("expected InvalidLanguage, got {:?}", other.map(|s| s.rows.len()))
+        }
+        match Sheet::from_directory(&dir, "quest/Test", SheetLanguage::English) {
+            Err(FFXIVError::IO(e)) => assert!(e.to_string().contains("Test_0_en.exd")),
+            other => panic!("expected a missing page, got {:?}", other.map(|s| s.rows.len()))
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+use super::ex::*;
+use super::decoding::{EXHF_MAGIC, EXDF_MAGIC};
+use super::{Sheet, SheetRow, SheetValue, SheetError, SheetErrorType};
+use byteorder::{LittleEndian, BigEndian};
+use byteorder::ByteOrder;
+use ::FFXIVError;
+
+use std::collections::{BTreeMap, HashSet};
+use std::rc::Rc;
+
+const EXH_VERSION: u16 = 3;
+const EXD_VERSION: u16 = 2;
+const EXD_HEADER_SIZE: usize = 0x20;
+/// Size of the header in front of every row: a u32 data size and a u16 sub-row count
+const ROW_HEADER_SIZE: usize = 6;
+
+/// The files making up an encoded sheet: the EXHF header and one EXDF file per page,
+/// in the order of `SheetInfo.pages`.
+pub struct EncodedSheet {
+    pub header: Vec<u8>,
+    pub pages: Vec<Vec<u8>>
+}
+
+/// Only sheets with one row per id can be written; sub-row sheets (variant 2) lay out
+/// their rows differently.
+fn check_variant(info: &SheetInfo) -> Result<(), FFXIVError> {
+    if info.variant != 1 {
+        return Err(FFXIVError::EncodingEXD(Box::new(FFXIVError::Custom(
+            format!("Sheets of variant {} can't be encoded, only variant 1", info.variant)))));
+    }
+    Ok(())
+}
+
+/// Encodes a SheetInfo into an EXHF header.
+pub fn encode_sheet_info(info: &SheetInfo) -> Result<Vec<u8>, FFXIVError> {
+    check_variant(info)?;
+    let mut languages: Vec<u16> = info.languages.iter().map(|l| l.get_language_id()).collect();
+    languages.sort();
+
+    let size = 0x20 + 4 * info.data_types.len() + 8 * info.pages.len() + 2 * languages.len();
+    let mut exh = vec![0u8; size];
+    BigEndian::write_u32(&mut exh[0x0..0x4], EXHF_MAGIC);
+    BigEndian::write_u16(&mut exh[0x4..0x6], EXH_VERSION);
+    BigEndian::write_u16(&mut exh[0x6..0x8], info.data_set_size);
+    BigEndian::write_u16(&mut exh[0x8..0xa], info.data_types.len() as u16);
+    BigEndian::write_u16(&mut exh[0xa..0xc], info.pages.len() as u16);
+    BigEndian::write_u16(&mut exh[0xc..0xe], languages.len() as u16);
+    exh[0x11] = info.variant;
+    BigEndian::write_u32(&mut exh[0x14..0x18], info.num_entries);
+
+    let mut pos: usize = 0x20;
+    for data_type in &info.data_types {
+        BigEndian::write_u16(&mut exh[pos..pos + 2], data_type.get_type_code());
+        BigEndian::write_u16(&mut exh[pos + 2..pos + 4], data_type.get_pointer());
+        pos += 4;
+    }
+    for page in &info.pages {
+        BigEndian::write_u32(&mut exh[pos..pos + 4], page.page_entry);
+        BigEndian::write_u32(&mut exh[pos + 4..pos + 8], page.page_size);
+        pos += 8;
+    }
+    for language in languages {
+        LittleEndian::write_u16(&mut exh[pos..pos + 2], language);
+        pos += 2;
+    }
+    Ok(exh)
+}
+
+/// Encodes rows into EXDF pages. Each row is the fixed-length data followed by its
+/// strings, as in `SheetRow.by`. Rows are placed in the page whose range contains their
+/// id; a row that fits no page, or an id given twice, is an error.
+pub fn encode_sheet_pages<'a, I>(info: &SheetInfo, rows: I) -> Result<Vec<Vec<u8>>, FFXIVError>
+    where I: IntoIterator<Item = (usize, &'a [u8])> {
+    check_variant(info)?;
+    let mut paged_rows: Vec<Vec<(usize, &'a [u8])>> = info.pages.iter().map(|_| Vec::new()).collect();
+    let mut ids = HashSet::<usize>::new();
+    for (index, data) in rows {
+        if !ids.insert(index) {
+            return Err(FFXIVError::EncodingEXD(Box::new(FFXIVError::Custom(
+                format!("Row {} is given more than once", index)))));
+        }
+        let page = info.pages.iter().position(|p|
+            index >= p.page_entry as usize && index < p.page_entry as usize + p.page_size as usize);
+        match page {
+            Some(page) => paged_rows[page].push((index, data)),
+            None => return Err(FFXIVError::EncodingEXD(Box::new(FFXIVError::Custom(
+                format!("Row {} is not inside any page of the sheet", index)))))
+        }
+    }
+    Ok(paged_rows.iter_mut().map(|rows| {
+        rows.sort_by_key(|r| r.0);
+        encode_page(rows)
+    }).collect())
+}
+
+fn encode_page(rows: &[(usize, &[u8])]) -> Vec<u8> {
+    let offset_size = 8 * rows.len();
+    let mut data = Vec::<u8>::new();
+    let mut offsets = vec![0u8; offset_size];
+    for (i, (index, row)) in rows.iter().enumerate() {
+        let row_offset = EXD_HEADER_SIZE + offset_size + data.len();
+        BigEndian::write_u32(&mut offsets[i * 8..i * 8 + 4], *index as u32);
+        BigEndian::write_u32(&mut offsets[i * 8 + 4..i * 8 + 8], row_offset as u32);
+
+        // Rows are padded so that the next row starts on a 4-byte boundary
+        let padding = (4 - (ROW_HEADER_SIZE + row.len()) % 4) % 4;
+        let mut row_header = [0u8; ROW_HEADER_SIZE];
+        BigEndian::write_u32(&mut row_header[0..4], (row.len() + padding) as u32);
+        BigEndian::write_u16(&mut row_header[4..6], 1);
+        data.extend_from_slice(&row_header);
+        data.extend_from_slice(row);
+        data.resize(data.len() + padding, 0);
+    }
+
+    let mut exd = vec![0u8; EXD_HEADER_SIZE];
+    BigEndian::write_u32(&mut exd[0x0..0x4], EXDF_MAGIC);
+    BigEndian::write_u16(&mut exd[0x4..0x6], EXD_VERSION);
+    BigEndian::write_u32(&mut exd[0x8..0xc], offset_size as u32);
+    BigEndian::write_u32(&mut exd[0xc..0x10], data.len() as u32);
+    exd.extend_from_slice(&offsets);
+    exd.extend_from_slice(&data);
+    exd
+}
+
+/// Encodes a sheet into its EXHF header and EXDF pages.
+pub fn encode_sheet(info: &SheetInfo, sheet: &Sheet) -> Result<EncodedSheet, FFXIVError> {
+    let pages = encode_sheet_pages(info, sheet.rows.iter().map(|(index, row)| (*index, row.by.as_slice())))?;
+    Ok(EncodedSheet { header: encode_sheet_info(info)?, pages })
+}
+
+/// Builds the bytes of a row from cell values, laying out the fixed-length data and
+/// appending strings to the row's string heap.
+pub struct RowBuilder {
+    types: Rc<Vec<SheetDataType>>,
+    fixed: Vec<u8>,
+    /// The encoded value of each string cell that has been set, keyed by cell. The heap is
+    /// laid out from these in `build`, so replacing a string leaves nothing behind.
+    strings: BTreeMap<usize, Vec<u8>>
+}
+
+impl RowBuilder {
+    /// Creates a builder for an empty row: all numbers zero, all strings empty.
+    pub fn new(types: Rc<Vec<SheetDataType>>, data_set_size: u16) -> RowBuilder {
+        RowBuilder { types, fixed: vec![0u8; data_set_size as usize], strings: BTreeMap::new() }
+    }
+
+    /// Creates a builder holding the values of an existing row, so that some cells can be
+    /// replaced. The string heap is rebuilt from the row's strings.
+    pub fn from_row(row: &SheetRow, data_set_size: u16) -> Result<RowBuilder, SheetError> {
+        let mut builder = RowBuilder::new(row.types.clone(), data_set_size);
+        let fixed_end = (data_set_size as usize).min(row.by.len());
+        builder.fixed[..fixed_end].copy_from_slice(&row.by[..fixed_end]);
+        for cell in 0..row.types.len() {
+            if let SheetDataType::String(_) = row.types[cell] {
+                builder.set(cell, &row.read_cell_data(cell)?)?;
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Sets a cell. The value must match the type of the column.
+    pub fn set(&mut self, cell: usize, value: &SheetValue) -> Result<(), SheetError> {
+        let data_type = *self.types.get(cell)
+            .ok_or(SheetError { error_type: SheetErrorType::CellOutOfBounds })?;
+        let pointer = data_type.get_pointer() as usize;
+        let width = data_type.get_size() as usize;
+        if pointer + width > self.fixed.len() {
+            return Err(SheetError { error_type: SheetErrorType::CellOutOfBounds });
+        }
+        let slot = &mut self.fixed[pointer..pointer + width];
+        match (data_type, value) {
+            (SheetDataType::String(_), SheetValue::String(v)) => {
+                self.strings.insert(cell, v.encode());
+            },
+            (SheetDataType::Bool(_), SheetValue::Bool(v)) => slot[0] = *v as u8,
+            (SheetDataType::Byte(_), SheetValue::Byte(v)) => slot[0] = *v as u8,
+            (SheetDataType::UByte(_), SheetValue::UByte(v)) => slot[0] = *v,
+            (SheetDataType::Short(_), SheetValue::Short(v)) => BigEndian::write_i16(slot, *v),
+            (SheetDataType::UShort(_), SheetValue::UShort(v)) => BigEndian::write_u16(slot, *v),
+            (SheetDataType::Int(_), SheetValue::Int(v)) => BigEndian::write_i32(slot, *v),
+            (SheetDataType::UInt(_), SheetValue::UInt(v)) => BigEndian::write_u32(slot, *v),
+            (SheetDataType::Float(_), SheetValue::Float(v)) => BigEndian::write_f32(slot, *v),
+            (SheetDataType::PackedInts(_), SheetValue::PackedInts(v)) => BigEndian::write_u64(slot, *v),
+            (SheetDataType::BitFlags(b_info), SheetValue::BitFlags(v)) => {
+                if *v {
+                    slot[0] |= 1 << b_info.bit;
+                } else {
+                    slot[0] &= !(1 << b_info.bit);
+                }
+            },
+            _ => return Err(SheetError { error_type: SheetErrorType::Incompatible })
+        }
+        Ok(())
+    }
+
+    /// Gets the bytes of the row: the fixed-length data followed by the string heap.
+    pub fn build(&self) -> Vec<u8> {
+        let mut by = self.fixed.clone();
+        let mut heap = Vec::<u8>::new();
+        for (cell, string) in &self.strings {
+            let pointer = self.types[*cell].get_pointer() as usize;
+            BigEndian::write_u32(&mut by[pointer..pointer + 4], heap.len() as u32);
+            heap.extend_from_slice(string);
+            heap.push(0);
+        }
+        by.extend_from_slice(&heap);
+        by
+    }
+
+    /// Builds the row as a SheetRow sharing the builder's column types.
+    pub fn build_row(&self) -> SheetRow {
+        SheetRow { by: self.build(), types: self.types.clone(), schema: None }
+    }
+}
+
+#[cfg(test)]
+mod encode_test {
+    use super::*;
+    use super::super::decoding::{decode_sheet_info, decode_sheet_from_bytes, decode_sheet_ref};
+    use super::super::sestring::SeString;
+    use std::collections::HashSet;
+
+    fn test_info() -> SheetInfo {
+        let mut languages = HashSet::new();
+        languages.insert(SheetLanguage::English);
+        languages.insert(SheetLanguage::Japanese);
+        SheetInfo {
+            data_types: vec![
+                SheetDataType::String(StringInfo { pointer: 0, strings_offset: 12 }),
+                SheetDataType::UShort(BasicInfo { pointer: 4 }),
+                SheetDataType::BitFlags(BitFlagsInfo { pointer: 6, bit: 0 }),
+                SheetDataType::BitFlags(BitFlagsInfo { pointer: 6, bit: 3 }),
+                SheetDataType::Float(BasicInfo { pointer: 8 }),
+                SheetDataType::String(StringInfo { pointer: 8, strings_offset: 12 }),
+            ],
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }, SheetPage { page_entry: 10, page_size: 10 }],
+            languages,
+            num_entries: 3,
+            data_set_size: 12,
+            variant: 1
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let info = test_info();
+        let decoded = decode_sheet_info(&encode_sheet_info(&info).unwrap()).unwrap();
+        assert_eq!(decoded.data_set_size, 12);
+        assert_eq!(decoded.variant, 1);
+        assert_eq!(decoded.num_entries, 3);
+        assert_eq!(decoded.languages, info.languages);
+        assert_eq!(decoded.pages.len(), 2);
+        assert_eq!(decoded.pages[1].page_entry, 10);
+        let codes: Vec<(u16, u16)> = decoded.data_types.iter().map(|t| (t.get_type_code(), t.get_pointer())).collect();
+        assert_eq!(codes, vec![(0x0, 0), (0x5, 4), (0x19, 6), (0x1c, 6), (0x9, 8), (0x0, 8)]);
+    }
+
+    #[test]
+    fn rows_round_trip() {
+        let mut info = test_info();
+        info.data_types.pop();
+        let types = Rc::new(info.data_types.clone());
+        let rows: Vec<(usize, Vec<SheetValue>)> = vec![
+            (1, vec![SheetValue::String(SeString::from("Potion")), SheetValue::UShort(30),
+                     SheetValue::BitFlags(true), SheetValue::BitFlags(false), SheetValue::Float(1.5)]),
+            (4, vec![SheetValue::String(SeString::decode(&[b'a', 0x02, 0x10, 0x01, 0x03, b'b']).unwrap()),
+                     SheetValue::UShort(0), SheetValue::BitFlags(false), SheetValue::BitFlags(true), SheetValue::Float(-2.0)]),
+            (12, vec![SheetValue::String(SeString::default()), SheetValue::UShort(65535),
+                      SheetValue::BitFlags(true), SheetValue::BitFlags(true), SheetValue::Float(0.0)]),
+        ];
+        let built: Vec<(usize, Vec<u8>)> = rows.iter().map(|(index, values)| {
+            let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+            for (cell, value) in values.iter().enumerate() {
+                builder.set(cell, value).unwrap();
+            }
+            (*index, builder.build())
+        }).collect();
+
+        let encoded = encode_sheet_pages(&info, built.iter().map(|(i, by)| (*i, by.as_slice()))).unwrap();
+        assert_eq!(encoded.len(), 2);
+        let sheet = decode_sheet_from_bytes(&info, &encoded).unwrap();
+        assert_eq!(sheet.rows.keys().cloned().collect::<Vec<usize>>(), vec![1, 4, 12]);
+        for (index, values) in &rows {
+            assert_eq!(&sheet.rows[index].values().unwrap(), values);
+        }
+
+        // Borrowed rows read the same values straight out of the page buffers
+        let borrowed = decode_sheet_ref(&info, &encoded).unwrap();
+        assert_eq!(borrowed.keys().cloned().collect::<Vec<usize>>(), vec![1, 4, 12]);
+        for (index, values) in &rows {
+            assert_eq!(&borrowed[index].values().unwrap(), values);
+            assert_eq!(borrowed[index].by, sheet.rows[index].by.as_slice());
+        }
+        let page = encoded[0].as_ptr_range();
+        assert!(page.contains(&borrowed[&4].by.as_ptr()));
+
+        // Re-encoding decoded rows reproduces the same pages
+        let again = encode_sheet(&info, &sheet).unwrap();
+        assert_eq!(again.pages, encoded);
+    }
+
+    #[test]
+    fn rows_outside_pages_are_rejected() {
+        let info = test_info();
+        let row = vec![0u8; 12];
+        assert!(encode_sheet_pages(&info, vec![(25, row.as_slice())]).is_err());
+    }
+
+    #[test]
+    fn duplicate_rows_are_rejected() {
+        let info = test_info();
+        let row = vec![0u8; 12];
+        match encode_sheet_pages(&info, vec![(3, row.as_slice()), (1, row.as_slice()), (3, row.as_slice())]) {
+            Err(FFXIVError::EncodingEXD(_)) => (),
+            other => panic!("expected an encoding error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn sub_row_sheets_are_rejected() {
+        let mut info = test_info();
+        info.variant = 2;
+        let row = vec![0u8; 12];
+        assert!(encode_sheet_info(&info).is_err());
+        assert!(encode_sheet_pages(&info, vec![(1, row.as_slice())]).is_err());
+    }
+
+    #[test]
+    fn builder_rejects_mismatched_values() {
+        let info = test_info();
+        let mut builder = RowBuilder::new(Rc::new(info.data_types.clone()), info.data_set_size);
+        assert!(builder.set(1, &SheetValue::UInt(3)).is_err());
+        assert!(builder.set(9, &SheetValue::UShort(3)).is_err());
+    }
+
+    #[test]
+    fn replacing_strings_rebuilds_the_heap() {
+        let mut info = test_info();
+        info.data_types.pop();
+        let types = Rc::new(info.data_types.clone());
+        let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+        builder.set(0, &SheetValue::String(SeString::from("Potion"))).unwrap();
+        builder.set(1, &SheetValue::UShort(30)).unwrap();
+        let row = SheetRow { by: builder.build(), types: types.clone(), schema: None };
+        assert_eq!(row.by.len(), 12 + 7);
+
+        let mut edited = RowBuilder::from_row(&row, info.data_set_size).unwrap();
+        edited.set(0, &SheetValue::String(SeString::from("Ether"))).unwrap();
+        edited.set(0, &SheetValue::String(SeString::from("Elixir"))).unwrap();
+        let edited = SheetRow { by: edited.build(), types, schema: None };
+        assert_eq!(edited.by.len(), 12 + 7);
+        assert_eq!(edited.get::<String>(0).unwrap(), "Elixir");
+        assert_eq!(edited.get::<u16>(1).unwrap(), 30);
+    }
+}
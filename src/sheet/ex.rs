@@ -1,13 +1,42 @@
 use std::collections::HashSet;
 
+use ::FFXIVError;
+
+#[derive(Clone, Debug)]
 pub struct SheetInfo {
     pub data_types: Vec<SheetDataType>,
     pub pages: Vec<SheetPage>,
     pub languages: HashSet<SheetLanguage>,
-    pub num_entries: u32
+    pub num_entries: u32,
+    /// Size of the fixed-length part of each row. String data follows it.
+    pub data_set_size: u16,
+    /// 1 for sheets with plain rows, 2 for sheets with sub-rows.
+    pub variant: u8
 }
 
-#[derive(Hash, PartialEq, Eq, Debug)]
+impl SheetInfo {
+    /// Picks the first language of `preferences` that the sheet has.
+    pub fn pick_language(&self, preferences: &[SheetLanguage]) -> Option<SheetLanguage> {
+        preferences.iter().find(|l| self.languages.contains(l)).cloned()
+    }
+
+    /// Picks the first language of `preferences` that the sheet has, or fails with
+    /// `NoPreferredLanguage`.
+    pub fn preferred_language(&self, preferences: &[SheetLanguage]) -> Result<SheetLanguage, FFXIVError> {
+        self.pick_language(preferences)
+            .ok_or_else(|| FFXIVError::NoPreferredLanguage(preferences.to_vec(), self.languages.clone()))
+    }
+
+    /// Checks that the sheet has a language, failing with `InvalidLanguage` otherwise.
+    pub fn check_language(&self, language: SheetLanguage) -> Result<(), FFXIVError> {
+        if !self.languages.contains(&language) {
+            return Err(FFXIVError::InvalidLanguage(language, self.languages.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Copy)]
 pub enum SheetLanguage {
     None,
     Japanese,
@@ -20,6 +49,35 @@ pub enum SheetLanguage {
 }
 
 impl SheetLanguage {
+    /// Gets the language from the id used in the EXH language table.
+    pub fn from_language_id(id: u16) -> Option<SheetLanguage> {
+        match id {
+            0x0 => Some(SheetLanguage::None),
+            0x1 => Some(SheetLanguage::Japanese),
+            0x2 => Some(SheetLanguage::English),
+            0x3 => Some(SheetLanguage::German),
+            0x4 => Some(SheetLanguage::French),
+            0x5 => Some(SheetLanguage::ChineseS),
+            0x6 => Some(SheetLanguage::ChineseT),
+            0x7 => Some(SheetLanguage::Korean),
+            _ => None
+        }
+    }
+
+    /// Gets the id used for the language in the EXH language table.
+    pub fn get_language_id(&self) -> u16 {
+        match self {
+            SheetLanguage::None => 0x0,
+            SheetLanguage::Japanese => 0x1,
+            SheetLanguage::English => 0x2,
+            SheetLanguage::German => 0x3,
+            SheetLanguage::French => 0x4,
+            SheetLanguage::ChineseS => 0x5,
+            SheetLanguage::ChineseT => 0x6,
+            SheetLanguage::Korean => 0x7,
+        }
+    }
+
     pub fn get_language_code(&self) -> Option<String> {
         match self {
             SheetLanguage::None => None,
@@ -45,12 +103,34 @@ impl std::fmt::Display for SheetLanguage {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct SheetPage {
     pub page_entry: u32,
     pub page_size: u32
 }
 
-#[derive(Clone, Copy)]
+/// The path of the EXDF file holding a page of a sheet, e.g. `exd/Item_0_en.exd`.
+/// Sheets without languages drop the language suffix.
+pub fn page_file_name(sheet: &str, page: &SheetPage, language: SheetLanguage) -> String {
+    format!("exd/{}", exd_file_name(sheet, page, language))
+}
+
+/// The name of the EXDF file of a page relative to the `exd` folder, e.g. `Item_0_en.exd`.
+pub fn exd_file_name(sheet: &str, page: &SheetPage, language: SheetLanguage) -> String {
+    match language.get_language_code() {
+        Some(code) => format!("{}_{}_{}.exd", sheet, page.page_entry, code),
+        None => format!("{}_{}.exd", sheet, page.page_entry)
+    }
+}
+
+impl SheetPage {
+    /// Whether a row id falls in the range of ids this page holds.
+    pub fn contains(&self, row: usize) -> bool {
+        row >= self.page_entry as usize && row < self.page_entry as usize + self.page_size as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SheetDataType {
     String(StringInfo),
     Bool(BasicInfo),
@@ -81,21 +161,93 @@ impl SheetDataType {
             SheetDataType::BitFlags(b_info) => String::from(format!("bitflags[{}]", b_info.bit)),
         }
     }
+
+    /// Gets the code used for the type in the EXH column table.
+    pub fn get_type_code(&self) -> u16 {
+        match self {
+            SheetDataType::String(_) => 0x0,
+            SheetDataType::Bool(_) => 0x1,
+            SheetDataType::Byte(_) => 0x2,
+            SheetDataType::UByte(_) => 0x3,
+            SheetDataType::Short(_) => 0x4,
+            SheetDataType::UShort(_) => 0x5,
+            SheetDataType::Int(_) => 0x6,
+            SheetDataType::UInt(_) => 0x7,
+            SheetDataType::Float(_) => 0x9,
+            SheetDataType::PackedInts(_) => 0xb,
+            SheetDataType::BitFlags(b_info) => 0x19 + b_info.bit as u16,
+        }
+    }
+
+    /// Gets the offset of the column within the fixed-length part of a row.
+    pub fn get_pointer(&self) -> u16 {
+        match self {
+            SheetDataType::String(s_info) => s_info.pointer,
+            SheetDataType::BitFlags(b_info) => b_info.pointer,
+            SheetDataType::Bool(info) | SheetDataType::Byte(info) | SheetDataType::UByte(info)
+            | SheetDataType::Short(info) | SheetDataType::UShort(info) | SheetDataType::Int(info)
+            | SheetDataType::UInt(info) | SheetDataType::Float(info) | SheetDataType::PackedInts(info) => info.pointer,
+        }
+    }
+
+    /// Gets the number of bytes the column takes in the fixed-length part of a row.
+    /// String columns hold a 4-byte offset into the string heap.
+    pub fn get_size(&self) -> u16 {
+        match self {
+            SheetDataType::String(_) | SheetDataType::Int(_) | SheetDataType::UInt(_) | SheetDataType::Float(_) => 4,
+            SheetDataType::Short(_) | SheetDataType::UShort(_) => 2,
+            SheetDataType::PackedInts(_) => 8,
+            SheetDataType::Bool(_) | SheetDataType::Byte(_) | SheetDataType::UByte(_) | SheetDataType::BitFlags(_) => 1
+        }
+    }
+
+    /// Gets the type name SaintCoinach uses for the column in its CSV exports.
+    pub fn get_saint_coinach_header(&self) -> String {
+        match self {
+            SheetDataType::String(_) => String::from("str"),
+            SheetDataType::Bool(_) => String::from("bool"),
+            SheetDataType::Byte(_) => String::from("sbyte"),
+            SheetDataType::UByte(_) => String::from("byte"),
+            SheetDataType::Short(_) => String::from("int16"),
+            SheetDataType::UShort(_) => String::from("uint16"),
+            SheetDataType::Int(_) => String::from("int32"),
+            SheetDataType::UInt(_) => String::from("uint32"),
+            SheetDataType::Float(_) => String::from("single"),
+            SheetDataType::PackedInts(_) => String::from("int64"),
+            SheetDataType::BitFlags(b_info) => format!("bit&{:02X}", 1u8 << b_info.bit),
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct StringInfo {
     pub strings_offset: u32,
     pub pointer: u16
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct BitFlagsInfo {
     pub pointer: u16,
     pub bit: u8
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct BasicInfo {
     pub pointer: u16
 }
+
+#[cfg(test)]
+mod ex_test {
+    use super::*;
+
+    #[test]
+    fn language_fallback() {
+        let mut languages = HashSet::new();
+        languages.insert(SheetLanguage::ChineseS);
+        languages.insert(SheetLanguage::Japanese);
+        let info = SheetInfo { data_types: Vec::new(), pages: Vec::new(), languages, num_entries: 0, data_set_size: 0, variant: 1 };
+        let preferences = [SheetLanguage::English, SheetLanguage::Japanese, SheetLanguage::None];
+        assert_eq!(info.pick_language(&preferences), Some(SheetLanguage::Japanese));
+        assert_eq!(info.pick_language(&[SheetLanguage::English, SheetLanguage::None]), None);
+    }
+}
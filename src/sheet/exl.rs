@@ -0,0 +1,48 @@
+use ::FFXIVError;
+
+/// A sheet listed in `exd/root.exl`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExlEntry {
+    /// The sheet name, which may include a folder, e.g. `quest/001/ClsHyu001_00194`.
+    pub name: String,
+    /// The id of the sheet, or -1 for sheets that are only reached by name.
+    pub id: i32
+}
+
+/// Decodes an EXL sheet list. The file is text: an `EXLT,<version>` line followed by
+/// one `<name>,<id>` line per sheet.
+pub fn decode_exl(exl: &[u8]) -> Result<Vec<ExlEntry>, FFXIVError> {
+    let text = String::from_utf8_lossy(exl);
+    let mut lines = text.lines();
+    match lines.next() {
+        Some(header) if header.starts_with("EXLT") => (),
+        _ => return Err(FFXIVError::DecodingEXD(Box::new(FFXIVError::MagicMissing)))
+    }
+    let mut entries = Vec::new();
+    for line in lines.map(str::trim).filter(|l| !l.is_empty()) {
+        let (name, id) = line.rsplit_once(',').ok_or_else(|| malformed(line))?;
+        let id = id.trim().parse::<i32>().map_err(|_| malformed(line))?;
+        entries.push(ExlEntry { name: String::from(name), id });
+    }
+    Ok(entries)
+}
+
+fn malformed(line: &str) -> FFXIVError {
+    FFXIVError::DecodingEXD(Box::new(FFXIVError::Custom(format!("Malformed line in EXL: {:?}", line))))
+}
+
+#[cfg(test)]
+mod exl_test {
+    use super::*;
+
+    #[test]
+    fn sheet_list() {
+        let entries = decode_exl(b"EXLT,2\r\nAchievement,209\r\nquest/001/ClsHyu001_00194,-1\r\n\r\n").unwrap();
+        assert_eq!(entries, vec![
+            ExlEntry { name: String::from("Achievement"), id: 209 },
+            ExlEntry { name: String::from("quest/001/ClsHyu001_00194"), id: -1 },
+        ]);
+        assert!(decode_exl(b"Achievement,209\n").is_err());
+        assert!(decode_exl(b"EXLT,2\nAchievement\n").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Sheet, SheetError, SheetErrorType, SheetRowRef};
+use super::ex::SheetDataType;
+use super::row_reader::BitFlags;
+use super::schema::SheetSchema;
+
+/// One bit of a flag group: the BitFlags column that refers to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlagBit {
+    pub bit: u8,
+    pub column: usize,
+    /// The schema name of the column, if it has one.
+    pub name: Option<String>
+}
+
+/// The BitFlags columns that share a byte of the row. The header declares one column per
+/// bit, so a byte of eight flags shows up as eight columns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlagGroup {
+    pub pointer: u16,
+    /// The bits of the group, in column order.
+    pub bits: Vec<FlagBit>
+}
+
+/// Groups the BitFlags columns of a sheet by the byte they read, in the order the groups
+/// first appear.
+pub fn flag_groups(types: &[SheetDataType], schema: Option<&SheetSchema>) -> Vec<FlagGroup> {
+    let mut groups = Vec::<FlagGroup>::new();
+    for (column, data_type) in types.iter().enumerate() {
+        if let SheetDataType::BitFlags(info) = data_type {
+            let bit = FlagBit { bit: info.bit, column, name: schema.and_then(|s| s.column_name(column)).map(String::from) };
+            match groups.iter_mut().find(|g| g.pointer == info.pointer) {
+                Some(group) => group.bits.push(bit),
+                None => groups.push(FlagGroup { pointer: info.pointer, bits: vec![bit] })
+            }
+        }
+    }
+    groups
+}
+
+/// The flags of a group read from a row.
+#[derive(Clone, Debug)]
+pub struct FlagSet<'a> {
+    pub group: &'a FlagGroup,
+    pub flags: BitFlags
+}
+
+impl FlagGroup {
+    /// The first column of the group, where grouped exports put the byte.
+    pub fn first_column(&self) -> usize {
+        self.bits[0].column
+    }
+
+    /// The mask of the bits that have columns.
+    pub fn mask(&self) -> u8 {
+        self.bits.iter().fold(0, |mask, b| mask | 1 << b.bit)
+    }
+
+    /// Reads the byte of the group from a row.
+    pub fn read<'a>(&'a self, row: &SheetRowRef) -> Result<FlagSet<'a>, SheetError> {
+        match row.by.get(self.pointer as usize) {
+            Some(data) => Ok(FlagSet { group: self, flags: BitFlags { data: *data } }),
+            None => Err(SheetError { error_type: SheetErrorType::CellOutOfBounds })
+        }
+    }
+}
+
+impl<'a> FlagSet<'a> {
+    /// Gets a flag by its schema name.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        self.group.bits.iter()
+            .find(|b| b.name.as_ref().is_some_and(|n| n == name))
+            .map(|b| self.flags.get_bool(b.bit))
+    }
+
+    /// Gets a flag by its column.
+    pub fn get_column(&self, column: usize) -> Option<bool> {
+        self.group.bits.iter().find(|b| b.column == column).map(|b| self.flags.get_bool(b.bit))
+    }
+
+    /// Iterates over the bits of the group, in column order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a FlagBit, bool)> + '_ {
+        self.group.bits.iter().map(move |b| (b, self.flags.get_bool(b.bit)))
+    }
+}
+
+impl Sheet {
+    /// Groups the BitFlags columns of the sheet by byte, naming the bits from the schema.
+    pub fn flag_groups(&self) -> Vec<FlagGroup> {
+        flag_groups(&self.types, self.schema.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod flags_test {
+    use super::*;
+    use super::super::SheetRow;
+    use super::super::csv::{CsvOptions, read_csv_rows, write_csv_with_options};
+    use super::super::ex::{BasicInfo, BitFlagsInfo, SheetInfo, SheetLanguage, SheetPage};
+    use std::rc::Rc;
+
+    fn flag_sheet() -> Sheet {
+        let types = Rc::new(vec![
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 2, bit: 0 }),
+            SheetDataType::UShort(BasicInfo { pointer: 0 }),
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 3, bit: 0 }),
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 2, bit: 2 }),
+        ]);
+        let mut sheet = Sheet { rows: Default::default(), types: types.clone(), column_count: 4, schema: None };
+        sheet.rows.insert(1, SheetRow { by: vec![0, 7, 0b0000_0101, 1], types: types.clone(), schema: None });
+        sheet.rows.insert(2, SheetRow { by: vec![0, 9, 0b0000_0100, 0], types, schema: None });
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(r#"{ "sheet": "Test", "definitions": [
+            { "name": "IsUnique" }, { "index": 1, "name": "Level" }, { "index": 2, "name": "IsGlamourous" } ] }"#).unwrap());
+        sheet
+    }
+
+    #[test]
+    fn groups() {
+        let sheet = flag_sheet();
+        let groups = sheet.flag_groups();
+        assert_eq!(groups, vec![
+            FlagGroup { pointer: 2, bits: vec![
+                FlagBit { bit: 0, column: 0, name: Some(String::from("IsUnique")) },
+                FlagBit { bit: 2, column: 3, name: None },
+            ] },
+            FlagGroup { pointer: 3, bits: vec![FlagBit { bit: 0, column: 2, name: Some(String::from("IsGlamourous")) }] },
+        ]);
+        assert_eq!(groups[0].mask(), 0b101);
+        assert_eq!(groups[0].first_column(), 0);
+
+        let row = sheet.rows[&2].as_row_ref();
+        let flags = groups[0].read(&row).unwrap();
+        assert_eq!(flags.flags.data, 0b100);
+        assert_eq!(flags.get("IsUnique"), Some(false));
+        assert_eq!(flags.get("IsGlamourous"), None);
+        assert_eq!(flags.get_column(3), Some(true));
+        assert_eq!(flags.iter().map(|(b, v)| (b.column, v)).collect::<Vec<_>>(), vec![(0, false), (3, true)]);
+    }
+
+    #[test]
+    fn grouped_csv() {
+        let sheet = flag_sheet();
+        let options = CsvOptions { group_flags: true, ..CsvOptions::default() };
+        let mut out = Vec::new();
+        write_csv_with_options(&sheet, &mut out, &options).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_eq!(csv, "\"index\",\"IsUnique|bitflags[2]\",\"Level\",\"IsGlamourous\"\n\"1\",\"5\",\"7\",\"1\"\n\"2\",\"4\",\"9\",\"0\"\n");
+
+        let mut out = Vec::new();
+        write_csv_with_options(&sheet, &mut out, &CsvOptions { group_flags: true, ..CsvOptions::saint_coinach() }).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("key,0,1,2\r\n#,IsUnique|bitflags[2],Level,IsGlamourous\r\nint32,byte,uint16,byte\r\n"));
+
+        let info = SheetInfo {
+            data_types: sheet.types.to_vec(),
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }],
+            languages: vec![SheetLanguage::None].into_iter().collect(),
+            num_entries: 2,
+            data_set_size: 4,
+            variant: 1
+        };
+        let rows = read_csv_rows(&mut csv.as_bytes(), &info, &options).unwrap();
+        assert_eq!(rows.iter().map(|(id, by)| (*id, by[..4].to_vec())).collect::<Vec<_>>(),
+            sheet.rows.iter().map(|(id, row)| (*id, row.by.clone())).collect::<Vec<_>>());
+        assert!(read_csv_rows(&mut "\"index\",\"a\",\"b\",\"c\"\n1,300,7,1\n".as_bytes(), &info, &options).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use super::SheetRow;
+use super::ex::{SheetDataType, SheetInfo, SheetPage};
+use super::decoding::decode_sheet_page;
+use super::schema::SheetSchema;
+use ::FFXIVError;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+
+/// Reads the EXDF file of a page.
+pub type PageLoader<'a> = Box<dyn FnMut(&SheetPage) -> Result<Vec<u8>, FFXIVError> + 'a>;
+
+/// A sheet that reads its EXDF pages only when a row on them is requested. Decoded pages
+/// are cached, so each page is read at most once.
+pub struct LazySheet<'a> {
+    info: SheetInfo,
+    types: Rc<Vec<SheetDataType>>,
+    loader: PageLoader<'a>,
+    /// Decoded pages, keyed by their position in `info.pages`.
+    pages: HashMap<usize, IndexMap<usize, SheetRow>>
+}
+
+impl<'a> LazySheet<'a> {
+    /// Creates a lazy sheet. `loader` is called with a page of `info` to get the bytes of
+    /// its EXDF file.
+    pub fn new<F>(info: SheetInfo, loader: F) -> LazySheet<'a>
+        where F: FnMut(&SheetPage) -> Result<Vec<u8>, FFXIVError> + 'a {
+        let types = Rc::new(info.data_types.clone());
+        LazySheet { info, types, loader: Box::new(loader), pages: HashMap::new() }
+    }
+
+    pub fn info(&self) -> &SheetInfo {
+        &self.info
+    }
+
+    pub fn types(&self) -> &Rc<Vec<SheetDataType>> {
+        &self.types
+    }
+
+    /// Gets a row, reading the page it's on if that page isn't cached yet. Returns `None`
+    /// if no page covers the id, or the page doesn't hold that row.
+    pub fn get_row(&mut self, id: usize) -> Result<Option<&SheetRow>, FFXIVError> {
+        let page_index = match self.info.pages.iter().position(|p| p.contains(id)) {
+            Some(i) => i,
+            None => return Ok(None)
+        };
+        if !self.pages.contains_key(&page_index) {
+            let page = &self.info.pages[page_index];
+            let data = (self.loader)(page)?;
+            let rows = decode_sheet_page(page, &data, &self.types, self.info.data_set_size)?;
+            self.pages.insert(page_index, rows);
+        }
+        Ok(self.pages[&page_index].get(&id))
+    }
+
+    /// The number of pages that have been read so far.
+    pub fn loaded_pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+/// Streams the rows of a sheet in id order, reading one page at a time. Each page is
+/// decoded when the previous one runs out and dropped once its rows have been yielded,
+/// so only one page is held in memory however large the sheet is.
+pub struct SheetRowIter<'a> {
+    info: SheetInfo,
+    types: Rc<Vec<SheetDataType>>,
+    schema: Option<Rc<SheetSchema>>,
+    loader: PageLoader<'a>,
+    next_page: usize,
+    rows: indexmap::map::IntoIter<usize, SheetRow>,
+    failed: bool
+}
+
+impl<'a> SheetRowIter<'a> {
+    /// Creates an iterator over the rows of every page of `info`. `loader` is called with
+    /// each page in turn to get the bytes of its EXDF file.
+    pub fn new<F>(info: SheetInfo, loader: F) -> SheetRowIter<'a>
+        where F: FnMut(&SheetPage) -> Result<Vec<u8>, FFXIVError> + 'a {
+        let types = Rc::new(info.data_types.clone());
+        SheetRowIter { info, types, schema: None, loader: Box::new(loader), next_page: 0, rows: IndexMap::new().into_iter(), failed: false }
+    }
+
+    /// Attaches a column schema to the rows yielded from now on.
+    pub fn set_schema(&mut self, schema: &SheetSchema) {
+        self.schema = Some(Rc::new(schema.resolve(&self.types)));
+    }
+
+    pub fn info(&self) -> &SheetInfo {
+        &self.info
+    }
+
+    pub fn types(&self) -> &Rc<Vec<SheetDataType>> {
+        &self.types
+    }
+
+    pub fn schema(&self) -> Option<&SheetSchema> {
+        self.schema.as_deref()
+    }
+}
+
+/// Yields each row with its id. After a page fails to read or decode, the error is
+/// yielded and the iterator ends.
+impl<'a> Iterator for SheetRowIter<'a> {
+    type Item = Result<(usize, SheetRow), FFXIVError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((id, mut row)) = self.rows.next() {
+                row.schema = self.schema.clone();
+                return Some(Ok((id, row)));
+            }
+            if self.failed || self.next_page >= self.info.pages.len() {
+                return None;
+            }
+            let page = &self.info.pages[self.next_page];
+            self.next_page += 1;
+            let rows = (self.loader)(page).and_then(|data| decode_sheet_page(page, &data, &self.types, self.info.data_set_size));
+            match rows {
+                Ok(rows) => self.rows = rows.into_iter(),
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod lazy_test {
+    use super::*;
+    use super::super::ex::{BasicInfo, SheetLanguage};
+    use super::super::encoding::{encode_sheet_pages, RowBuilder};
+    use super::super::SheetValue;
+    use super::super::csv::{CsvOptions, write_csv_stream, write_csv_with_options};
+    use std::cell::Cell;
+    use std::collections::HashSet;
+
+    /// A sheet of one uint column on two pages of 10 rows, with the encoded pages.
+    fn two_pages() -> (SheetInfo, Vec<Vec<u8>>) {
+        let mut languages = HashSet::new();
+        languages.insert(SheetLanguage::None);
+        let info = SheetInfo {
+            data_types: vec![SheetDataType::UInt(BasicInfo { pointer: 0 })],
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }, SheetPage { page_entry: 10, page_size: 10 }],
+            languages,
+            num_entries: 4,
+            data_set_size: 4,
+            variant: 1
+        };
+        let types = Rc::new(info.data_types.clone());
+        let rows: Vec<(usize, Vec<u8>)> = [1usize, 4, 12, 19].iter().map(|id| {
+            let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+            builder.set(0, &SheetValue::UInt(*id as u32 * 100)).unwrap();
+            (*id, builder.build())
+        }).collect();
+        let encoded = encode_sheet_pages(&info, rows.iter().map(|(id, by)| (*id, by.as_slice()))).unwrap();
+        (info, encoded)
+    }
+
+    #[test]
+    fn pages_are_read_on_demand() {
+        let (info, encoded) = two_pages();
+        let reads = Cell::new(0);
+        let mut sheet = LazySheet::new(info, |page| {
+            reads.set(reads.get() + 1);
+            Ok(encoded[page.page_entry as usize / 10].clone())
+        });
+        assert_eq!(sheet.loaded_pages(), 0);
+        assert_eq!(sheet.get_row(12).unwrap().unwrap().get::<u32>(0).unwrap(), 1200);
+        assert_eq!(sheet.get_row(19).unwrap().unwrap().get::<u32>(0).unwrap(), 1900);
+        assert_eq!(sheet.loaded_pages(), 1);
+        assert!(sheet.get_row(15).unwrap().is_none());
+        assert!(sheet.get_row(25).unwrap().is_none());
+        assert_eq!(sheet.get_row(4).unwrap().unwrap().get::<u32>(0).unwrap(), 400);
+        assert_eq!(sheet.loaded_pages(), 2);
+        drop(sheet);
+        assert_eq!(reads.get(), 2);
+    }
+
+    #[test]
+    fn rows_are_streamed_page_by_page() {
+        let (info, encoded) = two_pages();
+        let reads = Cell::new(0);
+        let mut rows = SheetRowIter::new(info.clone(), |page| {
+            reads.set(reads.get() + 1);
+            Ok(encoded[page.page_entry as usize / 10].clone())
+        });
+        assert_eq!(reads.get(), 0);
+        let (id, row) = rows.next().unwrap().unwrap();
+        assert_eq!((id, row.get::<u32>(0).unwrap()), (1, 100));
+        assert_eq!(reads.get(), 1);
+        let ids: Vec<usize> = rows.map(|r| r.unwrap().0).collect();
+        assert_eq!(ids, vec![4, 12, 19]);
+        assert_eq!(reads.get(), 2);
+
+        // The error of a page that can't be read ends the iteration
+        let mut failing = SheetRowIter::new(info, |page| match page.page_entry {
+            0 => Ok(encoded[0].clone()),
+            _ => Err(FFXIVError::FileNotFound)
+        });
+        assert_eq!(failing.by_ref().filter(Result::is_ok).count(), 2);
+        assert!(failing.next().is_none());
+    }
+
+    #[test]
+    fn streamed_exports_match_sheet_exports() {
+        let (info, encoded) = two_pages();
+        let schema = SheetSchema::from_saint_coinach_json(r#"{ "sheet": "Test", "definitions": [ { "name": "Value" } ] }"#).unwrap();
+        let mut sheet = super::super::decoding::decode_sheet_from_bytes(&info, &encoded).unwrap();
+        sheet.set_schema(&schema);
+        let stream = || {
+            let mut rows = SheetRowIter::new(info.clone(), |page| Ok(encoded[page.page_entry as usize / 10].clone()));
+            rows.set_schema(&schema);
+            rows
+        };
+
+        let (mut expected, mut streamed) = (Vec::new(), Vec::new());
+        super::super::write_json(&sheet, &mut expected).unwrap();
+        super::super::write_json_stream(stream(), &mut streamed).unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), String::from_utf8(expected).unwrap());
+
+        let (mut expected, mut streamed) = (Vec::new(), Vec::new());
+        super::super::write_ndjson(&sheet, &mut expected).unwrap();
+        super::super::write_ndjson_stream(stream(), &mut streamed).unwrap();
+        assert_eq!(streamed, expected);
+
+        let options = CsvOptions::saint_coinach();
+        let (mut expected, mut streamed) = (Vec::new(), Vec::new());
+        write_csv_with_options(&sheet, &mut expected, &options).unwrap();
+        write_csv_stream(stream(), &mut streamed, &options).unwrap();
+        assert_eq!(streamed, expected);
+    }
+}
@@ -0,0 +1,153 @@
+use super::{Sheet, SheetError, SheetErrorType, SheetRow, SheetRowRef, SheetValue};
+use super::schema::{ColumnKey, ColumnLink};
+use ::FFXIVError;
+
+use std::rc::Rc;
+
+/// Something sheets can be loaded from by name, with their schema attached.
+/// `cache::SheetCache` implements this with a cache of every sheet it has loaded.
+pub trait SheetProvider {
+    fn sheet(&self, name: &str) -> Result<Rc<Sheet>, FFXIVError>;
+}
+
+/// A row found by following a link column.
+pub struct LinkedRow {
+    /// The name of the sheet the row is in, as written in the link.
+    pub sheet_name: String,
+    pub id: usize,
+    pub sheet: Rc<Sheet>
+}
+
+impl LinkedRow {
+    pub fn row(&self) -> &SheetRow {
+        &self.sheet.rows[&self.id]
+    }
+}
+
+impl<'a> SheetRowRef<'a> {
+    /// Follows a link column to the row it refers to. Multi-target links return the row
+    /// from the first target sheet that has it. Conditional links pick their targets by
+    /// the value of another column of this row. Returns `None` if no target has the row.
+    pub fn resolve(&self, column: impl ColumnKey, sheets: &dyn SheetProvider) -> Result<Option<LinkedRow>, FFXIVError> {
+        let cell = column.column_index(self.schema)?;
+        let link = self.schema.and_then(|s| s.column(cell)).and_then(|c| c.link.as_ref())
+            .ok_or(SheetError { error_type: SheetErrorType::NotALink })?;
+        let key = self.get::<SheetValue>(cell)?.as_i64()
+            .ok_or(SheetError { error_type: SheetErrorType::Incompatible })?;
+        if key < 0 {
+            return Ok(None);
+        }
+
+        let targets: &[String] = match link {
+            ColumnLink::Sheet(target) => std::slice::from_ref(target),
+            ColumnLink::Multi(targets) => targets,
+            ColumnLink::Conditional(cases) => {
+                let mut chosen = None;
+                for case in cases {
+                    let applies = match &case.when {
+                        Some((condition, value)) => self.get::<SheetValue>(condition)?.as_i64() == Some(*value),
+                        None => true
+                    };
+                    if applies {
+                        chosen = Some(case.targets.as_slice());
+                        break;
+                    }
+                }
+                match chosen {
+                    Some(targets) => targets,
+                    None => return Ok(None)
+                }
+            }
+        };
+
+        for target in targets {
+            let sheet = sheets.sheet(target)?;
+            if sheet.rows.contains_key(&(key as usize)) {
+                return Ok(Some(LinkedRow { sheet_name: target.clone(), id: key as usize, sheet }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl SheetRow {
+    /// Follows a link column to the row it refers to. See `SheetRowRef::resolve`.
+    pub fn resolve(&self, column: impl ColumnKey, sheets: &dyn SheetProvider) -> Result<Option<LinkedRow>, FFXIVError> {
+        self.as_row_ref().resolve(column, sheets)
+    }
+}
+
+#[cfg(test)]
+mod link_test {
+    use super::*;
+    use super::super::test_sheet;
+    use super::super::ex::{BasicInfo, SheetDataType};
+    use super::super::schema::SheetSchema;
+    use std::collections::HashMap;
+    use std::cell::Cell;
+
+    struct TestSheets {
+        sheets: HashMap<String, Rc<Sheet>>,
+        loads: Cell<usize>
+    }
+
+    impl SheetProvider for TestSheets {
+        fn sheet(&self, name: &str) -> Result<Rc<Sheet>, FFXIVError> {
+            self.loads.set(self.loads.get() + 1);
+            self.sheets.get(name).cloned().ok_or(FFXIVError::FileNotFound)
+        }
+    }
+
+    /// A sheet of rows with two ushort columns.
+    fn sheet(rows: &[(usize, u16, u16)], schema: Option<&str>) -> Sheet {
+        let mut sheet = test_sheet(vec![SheetDataType::UShort(BasicInfo { pointer: 0 }), SheetDataType::UShort(BasicInfo { pointer: 2 })],
+            rows.iter().map(|(id, a, b)| (*id, vec![SheetValue::UShort(*a), SheetValue::UShort(*b)])));
+        if let Some(json) = schema {
+            sheet.set_schema(&SheetSchema::from_saint_coinach_json(json).unwrap());
+        }
+        sheet
+    }
+
+    fn provider() -> TestSheets {
+        let mut sheets = HashMap::new();
+        sheets.insert(String::from("Item"), Rc::new(sheet(&[(5, 50, 0), (9, 90, 0)], None)));
+        sheets.insert(String::from("EventItem"), Rc::new(sheet(&[(7, 70, 0)], None)));
+        sheets.insert(String::from("Action"), Rc::new(sheet(&[(5, 55, 0)], None)));
+        TestSheets { sheets, loads: Cell::new(0) }
+    }
+
+    #[test]
+    fn single_and_multi_links() {
+        let recipes = sheet(&[(1, 9, 7)], Some(r#"{ "sheet": "Recipe", "definitions": [
+            { "name": "ItemResult", "converter": { "type": "link", "target": "Item" } },
+            { "index": 1, "name": "Reward", "converter": { "type": "multiref", "targets": [ "Item", "EventItem" ] } }
+        ] }"#));
+        let sheets = provider();
+        let row = &recipes.rows[&1];
+
+        let result = row.resolve("ItemResult", &sheets).unwrap().unwrap();
+        assert_eq!((result.sheet_name.as_str(), result.id), ("Item", 9));
+        assert_eq!(result.row().get::<u16>(0).unwrap(), 90);
+
+        let reward = row.resolve("Reward", &sheets).unwrap().unwrap();
+        assert_eq!((reward.sheet_name.as_str(), reward.id), ("EventItem", 7));
+        assert_eq!(sheets.loads.get(), 3);
+    }
+
+    #[test]
+    fn conditional_links() {
+        let schema = r#"{ "sheet": "Quest", "definitions": [
+            { "name": "Target", "converter": { "type": "complexlink", "links": [
+                { "when": { "key": "Kind", "value": 1 }, "sheet": "Item" },
+                { "when": { "key": "Kind", "value": 2 }, "sheet": "Action" }
+            ] } },
+            { "index": 1, "name": "Kind" }
+        ] }"#;
+        let quests = sheet(&[(1, 5, 1), (2, 5, 2), (3, 5, 3)], Some(schema));
+        let sheets = provider();
+        assert_eq!(quests.rows[&1].resolve(0, &sheets).unwrap().unwrap().sheet_name, "Item");
+        assert_eq!(quests.rows[&2].resolve(0, &sheets).unwrap().unwrap().sheet_name, "Action");
+        assert!(quests.rows[&3].resolve(0, &sheets).unwrap().is_none());
+        assert!(quests.rows[&1].resolve("Kind", &sheets).is_err());
+    }
+}
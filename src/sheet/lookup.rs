@@ -0,0 +1,154 @@
+use super::{Sheet, SheetError, SheetValue};
+use super::schema::ColumnKey;
+use super::sestring::SeString;
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A secondary index from the values of one column to the ids of the rows holding them,
+/// so rows can be found by name or code instead of id. String keys are matched on their
+/// text, without macros and without regard to case. Indexes serialize with serde, so they can be cached between runs.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnIndex {
+    column: usize,
+    #[serde(serialize_with = "serialize_entries", deserialize_with = "deserialize_entries")]
+    rows: HashMap<SheetValue, Vec<usize>>
+}
+
+/// Reduces a string key to its lowercased text, so macros such as soft hyphens don't
+/// keep a name from matching.
+fn normalize(value: SheetValue) -> SheetValue {
+    match value {
+        SheetValue::String(s) => SheetValue::String(SeString::from(s.text().to_lowercase().as_str())),
+        other => other
+    }
+}
+
+/// Map keys must be strings in formats such as JSON, so the map is written as a list of
+/// `[value, ids]` pairs, ordered by the first row id for stable output.
+fn serialize_entries<S: Serializer>(rows: &HashMap<SheetValue, Vec<usize>>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut entries: Vec<(&SheetValue, &Vec<usize>)> = rows.iter().collect();
+    entries.sort_by_key(|(_, ids)| ids.first().cloned());
+    serializer.collect_seq(entries)
+}
+
+fn deserialize_entries<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<SheetValue, Vec<usize>>, D::Error> {
+    let entries = Vec::<(SheetValue, Vec<usize>)>::deserialize(deserializer)?;
+    Ok(entries.into_iter().collect())
+}
+
+impl ColumnIndex {
+    /// The column the index was built over.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The ids of the rows holding a value, in sheet order.
+    pub fn get(&self, value: &SheetValue) -> &[usize] {
+        let found = match value {
+            SheetValue::String(_) => self.rows.get(&normalize(value.clone())),
+            other => self.rows.get(other)
+        };
+        found.map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// The ids of the rows whose string matches `text`, ignoring case.
+    pub fn get_str(&self, text: &str) -> &[usize] {
+        self.get(&SheetValue::String(SeString::from(text)))
+    }
+
+    /// The first row holding a value, for columns whose values are unique.
+    pub fn first(&self, value: &SheetValue) -> Option<usize> {
+        self.get(value).first().cloned()
+    }
+
+    /// The number of distinct values.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The values and row ids, with string keys reduced to their lowercased text.
+    pub fn as_map(&self) -> &HashMap<SheetValue, Vec<usize>> {
+        &self.rows
+    }
+}
+
+impl Sheet {
+    /// Builds an index over a column, by index or schema name. Strings without text are
+    /// left out, as most sheets use empty strings for unused rows.
+    pub fn build_index(&self, column: impl ColumnKey) -> Result<ColumnIndex, SheetError> {
+        let column = column.column_index(self.schema.as_deref())?;
+        let mut rows: HashMap<SheetValue, Vec<usize>> = HashMap::new();
+        for (id, row) in self.rows.iter() {
+            let value = normalize(row.get::<SheetValue>(column)?);
+            if value == SheetValue::String(SeString::from("")) {
+                continue;
+            }
+            rows.entry(value).or_default().push(*id);
+        }
+        Ok(ColumnIndex { column, rows })
+    }
+}
+
+#[cfg(test)]
+mod lookup_test {
+    use super::*;
+    use super::super::test_sheet;
+    use super::super::ex::{BasicInfo, SheetDataType, StringInfo};
+    use super::super::schema::SheetSchema;
+
+    /// Names are decoded, so they can hold macros.
+    fn sheet(rows: &[(usize, &str, f32)]) -> Sheet {
+        let mut sheet = test_sheet(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+            SheetDataType::Float(BasicInfo { pointer: 4 }),
+        ], rows.iter().map(|(id, name, value)|
+            (*id, vec![SheetValue::String(SeString::decode(name.as_bytes()).unwrap()), SheetValue::Float(*value)])));
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(
+            r#"{ "sheet": "Item", "definitions": [ { "name": "Name" }, { "index": 1, "name": "Weight" } ] }"#).unwrap());
+        sheet
+    }
+
+    #[test]
+    fn lookups() {
+        let sheet = sheet(&[(0, "", 0.0), (1, "Potion", 1.5), (2, "Ether", 1.5), (3, "POTION", -0.0), (4, "", 0.0)]);
+        let names = sheet.build_index("Name").unwrap();
+        assert_eq!(names.column(), 0);
+        assert_eq!(names.len(), 2);
+        assert_eq!(names.get_str("potion"), &[1, 3]);
+        assert_eq!(names.get(&SheetValue::String(SeString::from("Ether"))), &[2]);
+        assert_eq!(names.first(&SheetValue::String(SeString::from("ether"))), Some(2));
+        assert!(names.get_str("Elixir").is_empty());
+        assert!(names.get_str("").is_empty());
+
+        let weights = sheet.build_index(1).unwrap();
+        assert_eq!(weights.get(&SheetValue::Float(1.5)), &[1, 2]);
+        assert_eq!(weights.get(&SheetValue::Float(0.0)), &[0, 4]);
+        assert_eq!(weights.get(&SheetValue::Float(-0.0)), &[3]);
+        assert!(weights.get(&SheetValue::UInt(0)).is_empty());
+        assert!(sheet.build_index("Price").is_err());
+    }
+
+    #[test]
+    fn keys_ignore_macros() {
+        // A soft hyphen inside a name, and a string of only a line break
+        let names = sheet(&[(1, "Hi\x02\x16\x01\x03-Potion", 1.0), (2, "\x02\x10\x01\x03", 1.0)]).build_index("Name").unwrap();
+        assert_eq!(names.len(), 1);
+        assert_eq!(names.get_str("HI-POTION"), &[1]);
+    }
+
+    #[test]
+    fn serialization() {
+        let names = sheet(&[(1, "Potion", 1.5), (2, "Ether", 1.5), (3, "Potion", 2.0)]).build_index("Name").unwrap();
+        let json = serde_json::to_string(&names).unwrap();
+        assert_eq!(json, r#"{"column":0,"rows":[[{"String":"potion"},[1,3]],[{"String":"ether"},[2]]]}"#);
+        let read: ColumnIndex = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, names);
+        assert_eq!(read.get_str("ETHER"), &[2]);
+    }
+}
@@ -1,43 +1,95 @@
 mod info;
 mod row_reader;
 pub use self::row_reader::*;
+mod value;
+pub use self::value::*;
+pub mod cache;
 pub mod decoding;
+pub mod diff;
+pub mod directory;
+pub mod encoding;
+pub mod lazy;
+pub mod link;
+pub mod lookup;
+pub mod multilang;
+pub mod query;
 pub mod ex;
+pub mod exl;
+pub mod flags;
+pub mod sestring;
+pub mod schema;
+pub mod search;
+pub mod codegen;
+pub mod csv;
+pub mod validate;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
 use std::error::Error;
 
 use self::ex::SheetDataType;
+use self::schema::{ColumnKey, SheetSchema};
 
+use std::borrow::Borrow;
 use std::rc::Rc;
 use std::io::Write;
 
 use indexmap::IndexMap;
 
+#[cfg(feature = "derive")]
+pub use sqpack_blue_derive::SheetRow;
+
 
 pub struct Sheet {
     pub rows: IndexMap<usize, SheetRow>,
     pub types: Rc<Vec<SheetDataType>>,
-    pub column_count: u32
+    pub column_count: u32,
+    pub schema: Option<Rc<SheetSchema>>
 }
 
 pub struct SheetRow {
     pub by: Vec<u8>,
-    pub types: Rc<Vec<SheetDataType>>
+    pub types: Rc<Vec<SheetDataType>>,
+    pub schema: Option<Rc<SheetSchema>>
+}
+
+/// A row borrowed from the buffer it was read from, usually an EXDF page. `by` holds the
+/// fixed-length data followed by the row's string heap, the same as `SheetRow.by`.
+#[derive(Clone, Copy)]
+pub struct SheetRowRef<'a> {
+    pub by: &'a [u8],
+    pub types: &'a [SheetDataType],
+    pub schema: Option<&'a SheetSchema>
 }
 
 
 
 
+/// Reads a typed value from a cell. Since 0.1.0 this takes a borrowed row; owned rows
+/// convert with `SheetRow::as_row_ref`.
 pub trait FromSheet: Sized + std::fmt::Debug {
     type Error;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error>;
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error>;
+}
+
+/// Builds a typed value from a whole row. Usually implemented with `#[derive(SheetRow)]`
+/// from the `derive` feature. Like `FromSheet`, this takes a `SheetRowRef` since 0.1.0.
+pub trait FromSheetRow: Sized {
+    fn from_sheet_row(row: &SheetRowRef) -> Result<Self, SheetError>;
 }
 
 #[derive(Debug)]
 pub enum SheetErrorType {
     Incompatible,
     CellOutOfBounds,
-    StringProcessing
+    StringProcessing,
+    MalformedPayload,
+    UnknownColumn,
+    NotALink,
+    /// The row is shorter than the cell's position in the fixed-length data.
+    RowTooShort,
+    /// A string cell points past the end of the row's string heap.
+    StringOutOfHeap
 }
 
 #[derive(Debug)]
@@ -52,65 +104,175 @@ impl std::fmt::Display for SheetError {
         match self.error_type {
             SheetErrorType::Incompatible => write!(f, "The type was invalid."),
             SheetErrorType::StringProcessing => write!(f, "There was a problem converting the string to UTF-8."),
-            SheetErrorType::CellOutOfBounds => write!(f, "The specified cell was out of bounds.")
+            SheetErrorType::CellOutOfBounds => write!(f, "The specified cell was out of bounds."),
+            SheetErrorType::MalformedPayload => write!(f, "A macro payload in the string was malformed."),
+            SheetErrorType::UnknownColumn => write!(f, "The column name was not found in the sheet schema."),
+            SheetErrorType::NotALink => write!(f, "The column does not link to another sheet."),
+            SheetErrorType::RowTooShort => write!(f, "The row is too short to hold the cell."),
+            SheetErrorType::StringOutOfHeap => write!(f, "The string starts past the end of the row.")
+        }
+
+
+    }
+}
+
+impl Sheet {
+    /// Attaches a column schema to the sheet and all of its rows, so columns can be
+    /// addressed by name.
+    pub fn set_schema(&mut self, schema: &SheetSchema) {
+        let schema = Rc::new(schema.resolve(&self.types));
+        for row in self.rows.values_mut() {
+            row.schema = Some(schema.clone());
         }
+        self.schema = Some(schema);
+    }
 
+    /// Iterates over the rows of the sheet, converting each one to `T`.
+    pub fn typed_rows<'a, T: FromSheetRow + 'a>(&'a self) -> impl Iterator<Item = (usize, Result<T, SheetError>)> + 'a {
+        self.rows.iter().map(|(index, row)| (*index, T::from_sheet_row(&row.as_row_ref())))
+    }
 
+    /// Gets the schema name of a column, if the sheet has a schema that names it.
+    pub fn column_name(&self, cell: usize) -> Option<&str> {
+        self.schema.as_ref().and_then(|s| s.column_name(cell))
     }
 }
 
 impl SheetRow {
+    /// Borrows the row, for the readers shared with `SheetRowRef`.
+    pub fn as_row_ref(&self) -> SheetRowRef<'_> {
+        SheetRowRef { by: &self.by, types: &self.types, schema: self.schema.as_deref() }
+    }
+
     pub fn read_cell_data<T: FromSheet + std::fmt::Debug>(&self, cell: usize) -> Result<T, T::Error> {
+        T::from_ex_data(&self.as_row_ref(), cell)
+    }
+
+    /// Reads every cell of the row, in column order.
+    pub fn values(&self) -> Result<Vec<SheetValue>, SheetError> {
+        self.as_row_ref().values()
+    }
+
+    /// Reads a cell by index or by schema column name. Reading into `SheetValue`
+    /// works for any column type.
+    pub fn get<T: FromSheet<Error = SheetError>>(&self, column: impl ColumnKey) -> Result<T, SheetError> {
+        self.as_row_ref().get(column)
+    }
+}
+
+impl<'a> SheetRowRef<'a> {
+    pub fn read_cell_data<T: FromSheet + std::fmt::Debug>(&self, cell: usize) -> Result<T, T::Error> {
+        T::from_ex_data(self, cell)
+    }
+
+    /// Reads every cell of the row, in column order.
+    pub fn values(&self) -> Result<Vec<SheetValue>, SheetError> {
+        (0..self.types.len()).map(|cell| self.read_cell_data(cell)).collect()
+    }
+
+    /// Reads a cell by index or by schema column name.
+    pub fn get<T: FromSheet<Error = SheetError>>(&self, column: impl ColumnKey) -> Result<T, SheetError> {
+        let cell = column.column_index(self.schema)?;
         T::from_ex_data(self, cell)
     }
+
+    /// Copies the row out of its buffer.
+    pub fn to_sheet_row(&self, types: &Rc<Vec<SheetDataType>>) -> SheetRow {
+        SheetRow { by: self.by.to_vec(), types: types.clone(), schema: None }
+    }
 }
 
+/// Writes the sheet as CSV with every field quoted and a header of column names
+/// (or type names). See `csv::write_csv_with_options` for other dialects.
 pub fn write_csv(sheet: &Sheet, buffer: &mut Write) -> Result<(), ::FFXIVError> {
-    write!(buffer, "\"index\",")?;
-    for (index, typ) in sheet.types.iter().enumerate() {
-        if index == sheet.types.len() - 1 {
-            write!(buffer, "\"{}\"", typ.get_header())
-        } else {
-            write!(buffer, "\"{}\",", typ.get_header())
-        }?;
-    }
-    writeln!(buffer, "")?;;
-    for (index, row) in sheet.rows.iter() {
-        write!(buffer, "\"{}\",", index)?;
-        for (index_typ, typ) in row.types.iter().enumerate() {
-            use ::sheet::ex::SheetDataType;
-            use ::sheet::BitFlags;
-            match typ {
-                SheetDataType::String(_s_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<String>(index_typ)?),
-                SheetDataType::Bool(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<bool>(index_typ)?),
-                SheetDataType::Byte(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<i8>(index_typ)?),
-                SheetDataType::UByte(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<u8>(index_typ)?),
-                SheetDataType::Short(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<i16>(index_typ)?),
-                SheetDataType::UShort(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<u16>(index_typ)?),
-                SheetDataType::Int(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<i32>(index_typ)?),
-                SheetDataType::UInt(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<u32>(index_typ)?),
-                SheetDataType::Float(_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data::<f32>(index_typ)?),
-                SheetDataType::PackedInts(_info) =>
-                    write!(buffer, "\"unsupported\""),
-                SheetDataType::BitFlags(b_info) =>
-                    write!(buffer, "\"{}\"", row.read_cell_data
-                        ::<BitFlags>(index_typ)?.get_bool(b_info.bit.clone())),
-
-            }?;
-            if index_typ != row.types.len() - 1 {
-                write!(buffer, ",")?;
-            }
+    csv::write_csv_with_options(sheet, buffer, &csv::CsvOptions::default())
+}
+
+/// Writes the sheet as a JSON array of row objects. Each object has the row `id` followed by
+/// one member per column, named from the schema when the sheet has one and by index otherwise.
+pub fn write_json(sheet: &Sheet, buffer: &mut dyn Write) -> Result<(), ::FFXIVError> {
+    write_json_rows(sheet.schema.as_deref(), sheet.rows.iter().map(|(index, row)| Ok((*index, row))), buffer)
+}
+
+/// Writes the sheet as newline-delimited JSON, one row object per line. Row objects are
+/// the same as those of `write_json`.
+pub fn write_ndjson(sheet: &Sheet, buffer: &mut dyn Write) -> Result<(), ::FFXIVError> {
+    write_ndjson_rows(sheet.schema.as_deref(), sheet.rows.iter().map(|(index, row)| Ok((*index, row))), buffer)
+}
+
+/// Writes streamed rows as a JSON array, like `write_json`, holding one page at a time.
+pub fn write_json_stream(rows: lazy::SheetRowIter, buffer: &mut dyn Write) -> Result<(), ::FFXIVError> {
+    let schema = rows.schema().cloned();
+    write_json_rows(schema.as_ref(), rows, buffer)
+}
+
+/// Writes streamed rows as newline-delimited JSON, like `write_ndjson`, holding one page
+/// at a time.
+pub fn write_ndjson_stream(rows: lazy::SheetRowIter, buffer: &mut dyn Write) -> Result<(), ::FFXIVError> {
+    let schema = rows.schema().cloned();
+    write_ndjson_rows(schema.as_ref(), rows, buffer)
+}
+
+fn write_json_rows<R: Borrow<SheetRow>>(schema: Option<&SheetSchema>, rows: impl Iterator<Item = Result<(usize, R), ::FFXIVError>>,
+                                        buffer: &mut dyn Write) -> Result<(), ::FFXIVError> {
+    write!(buffer, "[")?;
+    for (position, row) in rows.enumerate() {
+        let (index, row) = row?;
+        if position != 0 {
+            write!(buffer, ",")?;
         }
-        writeln!(buffer, "")?;
+        writeln!(buffer)?;
+        write_json_row(schema, index, row.borrow(), buffer)?;
     }
+    writeln!(buffer)?;
+    writeln!(buffer, "]")?;
     Ok(())
-}
\ No newline at end of file
+}
+
+fn write_ndjson_rows<R: Borrow<SheetRow>>(schema: Option<&SheetSchema>, rows: impl Iterator<Item = Result<(usize, R), ::FFXIVError>>,
+                                          buffer: &mut dyn Write) -> Result<(), ::FFXIVError> {
+    for row in rows {
+        let (index, row) = row?;
+        write_json_row(schema, index, row.borrow(), buffer)?;
+        writeln!(buffer)?;
+    }
+    Ok(())
+}
+
+fn write_json_row(schema: Option<&SheetSchema>, index: usize, row: &SheetRow, buffer: &mut dyn Write) -> Result<(), ::FFXIVError> {
+    write!(buffer, "{{\"id\":{}", index)?;
+    for (cell, value) in row.values()?.iter().enumerate() {
+        let key = match schema.and_then(|s| s.column_name(cell)) {
+            Some(name) => String::from(name),
+            None => cell.to_string()
+        };
+        write!(buffer, ",")?;
+        ::serde_json::to_writer(&mut *buffer, &key).map_err(std::io::Error::from)?;
+        write!(buffer, ":")?;
+        ::serde_json::to_writer(&mut *buffer, &value.to_json()).map_err(std::io::Error::from)?;
+    }
+    write!(buffer, "}}")?;
+    Ok(())
+}
+
+/// Builds a sheet for tests from rows of cell values, laid out with `RowBuilder`. The
+/// fixed-length part of each row ends where the string heap starts, at the
+/// `strings_offset` of the string columns, or after the last column if there are none.
+#[cfg(test)]
+pub(crate) fn test_sheet<I>(types: Vec<SheetDataType>, rows: I) -> Sheet
+    where I: IntoIterator<Item = (usize, Vec<SheetValue>)> {
+    let data_set_size = types.iter().map(|t| match t {
+        SheetDataType::String(info) => info.strings_offset as u16,
+        other => (other.get_pointer() + other.get_size() + 3) & !3
+    }).max().unwrap_or(0);
+    let types = Rc::new(types);
+    let mut sheet = Sheet { rows: IndexMap::new(), types: types.clone(), column_count: types.len() as u32, schema: None };
+    for (id, values) in rows {
+        let mut builder = encoding::RowBuilder::new(types.clone(), data_set_size);
+        for (cell, value) in values.iter().enumerate() {
+            builder.set(cell, value).unwrap();
+        }
+        sheet.rows.insert(id, builder.build_row());
+    }
+    sheet
+}
@@ -0,0 +1,222 @@
+use super::{Sheet, SheetError, SheetErrorType, SheetValue};
+use super::csv::{csv_columns, CsvColumn, CsvOptions, CsvWriter};
+use super::ex::{SheetDataType, SheetLanguage};
+use super::schema::SheetSchema;
+use super::sestring::SeString;
+use ::FFXIVError;
+
+use std::io::Write;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+
+/// The same sheet loaded in every language it has. String cells are looked up per
+/// language; every other cell is expected to be the same in all of them.
+pub struct MultiLangSheet {
+    /// The sheet of each language, in the order of their language ids.
+    pub sheets: IndexMap<SheetLanguage, Sheet>,
+    pub types: Rc<Vec<SheetDataType>>
+}
+
+/// A non-string cell whose value differs between languages, or a row that is missing
+/// from some of them (`None`).
+#[derive(Debug, PartialEq)]
+pub struct Inconsistency {
+    pub row: usize,
+    pub column: usize,
+    pub values: Vec<(SheetLanguage, Option<SheetValue>)>
+}
+
+impl MultiLangSheet {
+    /// Merges sheets of the same layout. Fails if the column types differ.
+    pub fn from_sheets(mut sheets: Vec<(SheetLanguage, Sheet)>) -> Result<MultiLangSheet, SheetError> {
+        sheets.sort_by_key(|(language, _)| language.get_language_id());
+        let types = match sheets.first() {
+            Some((_, sheet)) => sheet.types.clone(),
+            None => Rc::new(Vec::new())
+        };
+        if sheets.iter().any(|(_, sheet)| sheet.types != types) {
+            return Err(SheetError { error_type: SheetErrorType::Incompatible });
+        }
+        Ok(MultiLangSheet { sheets: sheets.into_iter().collect(), types })
+    }
+
+    pub fn languages(&self) -> impl Iterator<Item = SheetLanguage> + '_ {
+        self.sheets.keys().cloned()
+    }
+
+    /// Attaches a column schema to the sheet of every language.
+    pub fn set_schema(&mut self, schema: &SheetSchema) {
+        for sheet in self.sheets.values_mut() {
+            sheet.set_schema(schema);
+        }
+    }
+
+    /// The ids of rows present in any language, in the order they are first seen.
+    pub fn row_ids(&self) -> Vec<usize> {
+        let mut ids = IndexMap::<usize, ()>::new();
+        for sheet in self.sheets.values() {
+            for id in sheet.rows.keys() {
+                ids.insert(*id, ());
+            }
+        }
+        ids.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Gets a string cell in every language that has the row.
+    pub fn get_strings(&self, row: usize, cell: usize) -> Result<IndexMap<SheetLanguage, SeString>, SheetError> {
+        match self.types.get(cell) {
+            Some(SheetDataType::String(_)) => (),
+            Some(_) => return Err(SheetError { error_type: SheetErrorType::Incompatible }),
+            None => return Err(SheetError { error_type: SheetErrorType::CellOutOfBounds })
+        }
+        let mut strings = IndexMap::new();
+        for (language, sheet) in &self.sheets {
+            if let Some(r) = sheet.rows.get(&row) {
+                strings.insert(*language, r.read_cell_data(cell)?);
+            }
+        }
+        Ok(strings)
+    }
+
+    /// Compares every non-string cell across languages.
+    pub fn check_consistency(&self) -> Result<Vec<Inconsistency>, SheetError> {
+        let mut found = Vec::new();
+        for id in self.row_ids() {
+            for (cell, data_type) in self.types.iter().enumerate() {
+                if let SheetDataType::String(_) = data_type {
+                    continue;
+                }
+                let mut values = Vec::with_capacity(self.sheets.len());
+                for (language, sheet) in &self.sheets {
+                    let value = match sheet.rows.get(&id) {
+                        Some(r) => Some(r.read_cell_data::<SheetValue>(cell)?),
+                        None => None
+                    };
+                    values.push((*language, value));
+                }
+                if values.iter().any(|(_, v)| *v != values[0].1) {
+                    found.push(Inconsistency { row: id, column: cell, values });
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Writes the sheet as CSV with the text of every language side by side. Each string
+/// column becomes one column per language, headed `<name>_<language code>`; other
+/// columns are written once, from the first language that has the row. The header,
+/// quoting and flag grouping follow `options`, as in `csv::write_csv_with_options`.
+pub fn write_csv_multilang(sheet: &MultiLangSheet, buffer: &mut dyn Write, options: &CsvOptions) -> Result<(), FFXIVError> {
+    let schema = sheet.sheets.values().next().and_then(|s| s.schema.as_deref());
+    // String columns are repeated for each language
+    let mut columns = Vec::<(CsvColumn, Option<SheetLanguage>)>::new();
+    for column in csv_columns(&sheet.types, schema, options.group_flags) {
+        match column {
+            CsvColumn::Cell(cell) if matches!(sheet.types[cell], SheetDataType::String(_)) =>
+                columns.extend(sheet.languages().map(|language| (CsvColumn::Cell(cell), Some(language)))),
+            other => columns.push((other, None))
+        }
+    }
+    let headers: Vec<(CsvColumn, Option<String>)> = columns.iter()
+        .map(|(c, language)| (c.clone(), language.and_then(|l| l.get_language_code())))
+        .collect();
+    let mut writer = CsvWriter::new(buffer, options.clone());
+    writer.write_header_columns(&headers, &sheet.types, schema)?;
+
+    for id in sheet.row_ids() {
+        let base = sheet.sheets.values().find_map(|s| s.rows.get(&id)).expect("row ids come from the sheets");
+        let mut values = Vec::<Option<SheetValue>>::with_capacity(columns.len());
+        for (column, language) in &columns {
+            values.push(match (column, language) {
+                (CsvColumn::Cell(cell), Some(language)) => match sheet.sheets[language].rows.get(&id) {
+                    Some(r) => Some(r.read_cell_data::<SheetValue>(*cell)?),
+                    None => None
+                },
+                (CsvColumn::Cell(cell), None) => Some(base.read_cell_data::<SheetValue>(*cell)?),
+                (CsvColumn::Flags(group), _) => base.by.get(group.pointer as usize).map(|b| SheetValue::UByte(*b))
+            });
+        }
+        writer.write_values(id, &values)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod multilang_test {
+    use super::*;
+    use super::super::test_sheet;
+    use super::super::ex::{BasicInfo, BitFlagsInfo, StringInfo};
+
+    fn sheet(rows: &[(usize, &str, u8)]) -> Sheet {
+        test_sheet(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+            SheetDataType::UByte(BasicInfo { pointer: 4 }),
+        ], rows.iter().map(|(id, text, value)| (*id, vec![SheetValue::String((*text).into()), SheetValue::UByte(*value)])))
+    }
+
+    fn merged() -> MultiLangSheet {
+        MultiLangSheet::from_sheets(vec![
+            (SheetLanguage::German, sheet(&[(1, "Trank", 5), (2, "Äther", 6)])),
+            (SheetLanguage::English, sheet(&[(1, "Potion", 5), (2, "Ether", 7), (3, "Elixir", 1)])),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn strings_by_language() {
+        let sheet = merged();
+        assert_eq!(sheet.languages().collect::<Vec<_>>(), vec![SheetLanguage::English, SheetLanguage::German]);
+        let strings = sheet.get_strings(2, 0).unwrap();
+        assert_eq!(strings[&SheetLanguage::English].text(), "Ether");
+        assert_eq!(strings[&SheetLanguage::German].text(), "Äther");
+        assert_eq!(sheet.get_strings(3, 0).unwrap().len(), 1);
+        assert!(sheet.get_strings(1, 1).is_err());
+    }
+
+    #[test]
+    fn inconsistent_cells() {
+        let found = merged().check_consistency().unwrap();
+        assert_eq!(found, vec![
+            Inconsistency { row: 2, column: 1, values: vec![
+                (SheetLanguage::English, Some(SheetValue::UByte(7))), (SheetLanguage::German, Some(SheetValue::UByte(6)))] },
+            Inconsistency { row: 3, column: 1, values: vec![
+                (SheetLanguage::English, Some(SheetValue::UByte(1))), (SheetLanguage::German, None)] },
+        ]);
+    }
+
+    #[test]
+    fn side_by_side_csv() {
+        let mut out = Vec::<u8>::new();
+        write_csv_multilang(&merged(), &mut out, &CsvOptions::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\"index\",\"string_en\",\"string_de\",\"uint8\"\n\
+            \"1\",\"Potion\",\"Trank\",\"5\"\n\"2\",\"Ether\",\"Äther\",\"7\"\n\"3\",\"Elixir\",\"\",\"1\"\n");
+    }
+
+    #[test]
+    fn side_by_side_csv_options() {
+        use super::super::csv::HeaderStyle;
+        let write = |sheet: &MultiLangSheet, options: &CsvOptions| {
+            let mut out = Vec::<u8>::new();
+            write_csv_multilang(sheet, &mut out, options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let mut sheet = merged();
+        let no_header = CsvOptions { header: HeaderStyle::None, ..CsvOptions::default() };
+        assert!(write(&sheet, &no_header).starts_with("\"1\",\"Potion\""));
+
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(r#"{ "sheet": "Item", "definitions": [ { "name": "Name" } ] }"#).unwrap());
+        assert!(write(&sheet, &CsvOptions::saint_coinach()).starts_with(
+            "key,0_en,0_de,1\r\n#,Name_en,Name_de,\r\nint32,str,str,byte\r\n1,\"Potion\",\"Trank\",5\r\n"));
+
+        // Flag columns sharing a byte are written once when grouped
+        let flagged = |text: &str| test_sheet(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 4, bit: 0 }),
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 4, bit: 1 }),
+        ], vec![(1, vec![SheetValue::String(text.into()), SheetValue::BitFlags(true), SheetValue::BitFlags(true)])]);
+        let sheet = MultiLangSheet::from_sheets(vec![(SheetLanguage::English, flagged("Potion")), (SheetLanguage::French, flagged("Potion"))]).unwrap();
+        let grouped = CsvOptions { group_flags: true, ..CsvOptions::default() };
+        assert_eq!(write(&sheet, &grouped), "\"index\",\"string_en\",\"string_fr\",\"bitflags\"\n\"1\",\"Potion\",\"Potion\",\"3\"\n");
+    }
+}
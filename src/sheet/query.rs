@@ -0,0 +1,443 @@
+use super::{Sheet, SheetError, SheetRow, SheetValue};
+use super::schema::{ColumnKey, SheetSchema};
+
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+
+/// A column named in a query, by index or by schema name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    Index(usize),
+    Name(String)
+}
+
+impl From<usize> for Column {
+    fn from(index: usize) -> Column {
+        Column::Index(index)
+    }
+}
+
+impl<'a> From<&'a str> for Column {
+    fn from(name: &'a str) -> Column {
+        Column::Name(String::from(name))
+    }
+}
+
+impl From<String> for Column {
+    fn from(name: String) -> Column {
+        Column::Name(name)
+    }
+}
+
+impl ColumnKey for &Column {
+    fn column_index(&self, schema: Option<&SheetSchema>) -> Result<usize, SheetError> {
+        match self {
+            Column::Index(index) => Ok(*index),
+            Column::Name(name) => name.as_str().column_index(schema)
+        }
+    }
+}
+
+/// A condition on the cells of a row.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Predicate {
+    /// The cell equals the value. Numbers compare by value whatever their width, and
+    /// strings compare by their display text.
+    Eq(Column, SheetValue),
+    /// The display text of the cell contains the text, ignoring case.
+    Contains(Column, String),
+    /// The cell is a number within the bounds.
+    Range(Column, Bound<f64>, Bound<f64>),
+    Not(Box<Predicate>),
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>)
+}
+
+impl Predicate {
+    pub fn eq(column: impl Into<Column>, value: SheetValue) -> Predicate {
+        Predicate::Eq(column.into(), value)
+    }
+
+    pub fn contains(column: impl Into<Column>, text: &str) -> Predicate {
+        Predicate::Contains(column.into(), String::from(text))
+    }
+
+    pub fn range<R: RangeBounds<f64>>(column: impl Into<Column>, range: R) -> Predicate {
+        Predicate::Range(column.into(), range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    /// Parses a predicate expression, e.g. `LevelItem >= 600 and Name contains "Potion"`.
+    /// Columns are schema names or `#n` for an index. Operators are `=`, `!=`, `<`, `<=`,
+    /// `>`, `>=` and `contains` (or `~`), combined with `and`, `or`, `not` and parentheses.
+    pub fn parse(expression: &str) -> Result<Predicate, QueryParseError> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let predicate = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            Some((offset, _)) => Err(QueryParseError { offset: *offset, message: String::from("unexpected input after expression") }),
+            None => Ok(predicate)
+        }
+    }
+
+    pub fn matches(&self, row: &SheetRow) -> Result<bool, SheetError> {
+        Ok(match self {
+            Predicate::Eq(column, value) => values_equal(&row.get(column)?, value),
+            Predicate::Contains(column, text) => row.get::<SheetValue>(column)?.to_string()
+                .to_lowercase().contains(&text.to_lowercase()),
+            Predicate::Range(column, start, end) => match row.get::<SheetValue>(column)?.as_f64() {
+                Some(v) => (*start, *end).contains(&v),
+                None => false
+            },
+            Predicate::Not(inner) => !inner.matches(row)?,
+            Predicate::And(all) => {
+                for p in all {
+                    if !p.matches(row)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            },
+            Predicate::Or(any) => {
+                for p in any {
+                    if p.matches(row)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+        })
+    }
+}
+
+fn values_equal(a: &SheetValue, b: &SheetValue) -> bool {
+    match (a.as_i64(), b.as_i64()) {
+        (Some(x), Some(y)) => x == y,
+        _ => match (a.as_f64(), b.as_f64()) {
+            (Some(x), Some(y)) => x == y,
+            _ => a.to_string() == b.to_string()
+        }
+    }
+}
+
+/// Orders numbers by value and everything else by display text.
+fn compare_values(a: &SheetValue, b: &SheetValue) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        _ => a.to_string().cmp(&b.to_string())
+    }
+}
+
+/// Filters, sorts and projects the rows of a sheet.
+pub struct Query<'a> {
+    sheet: &'a Sheet,
+    predicates: Vec<Predicate>,
+    order: Vec<(Column, bool)>,
+    columns: Option<Vec<Column>>
+}
+
+impl<'a> Query<'a> {
+    pub fn new(sheet: &'a Sheet) -> Query<'a> {
+        Query { sheet, predicates: Vec::new(), order: Vec::new(), columns: None }
+    }
+
+    /// Keeps only rows matching the predicate. Several filters must all match.
+    pub fn filter(mut self, predicate: Predicate) -> Query<'a> {
+        self.predicates.push(predicate);
+        self
+    }
+
+    /// Sorts by a column. Later sorts break ties of earlier ones. Rows are otherwise in
+    /// sheet order.
+    pub fn sort_by(mut self, column: impl Into<Column>, descending: bool) -> Query<'a> {
+        self.order.push((column.into(), descending));
+        self
+    }
+
+    /// Chooses the columns returned by `values`.
+    pub fn select<C: Into<Column>, I: IntoIterator<Item = C>>(mut self, columns: I) -> Query<'a> {
+        self.columns = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Runs the query, giving the matching rows.
+    pub fn rows(&self) -> Result<std::vec::IntoIter<(usize, &'a SheetRow)>, SheetError> {
+        let mut found = Vec::new();
+        'rows: for (id, row) in self.sheet.rows.iter() {
+            for predicate in &self.predicates {
+                if !predicate.matches(row)? {
+                    continue 'rows;
+                }
+            }
+            found.push((*id, row));
+        }
+        if !self.order.is_empty() {
+            let mut keyed = Vec::with_capacity(found.len());
+            for (id, row) in found {
+                let keys = self.order.iter().map(|(column, _)| row.get::<SheetValue>(column))
+                    .collect::<Result<Vec<_>, _>>()?;
+                keyed.push((keys, id, row));
+            }
+            keyed.sort_by(|a, b| {
+                a.0.iter().zip(&b.0).zip(&self.order)
+                    .map(|((x, y), (_, descending))| {
+                        let ordering = compare_values(x, y);
+                        if *descending { ordering.reverse() } else { ordering }
+                    })
+                    .find(|o| *o != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+            found = keyed.into_iter().map(|(_, id, row)| (id, row)).collect();
+        }
+        Ok(found.into_iter())
+    }
+
+    /// Runs the query, giving the selected columns of the matching rows (every column
+    /// if none were selected).
+    pub fn values(&self) -> Result<Vec<(usize, Vec<SheetValue>)>, SheetError> {
+        let mut values = Vec::new();
+        for (id, row) in self.rows()? {
+            let cells = match &self.columns {
+                Some(columns) => columns.iter().map(|c| row.get::<SheetValue>(c)).collect::<Result<Vec<_>, _>>()?,
+                None => row.values()?
+            };
+            values.push((id, cells));
+        }
+        Ok(values)
+    }
+}
+
+/// An error in a predicate expression, at a byte offset into it.
+#[derive(Debug, PartialEq)]
+pub struct QueryParseError {
+    pub offset: usize,
+    pub message: String
+}
+
+impl std::fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "at {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for QueryParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(&'static str),
+    Open,
+    Close
+}
+
+fn tokenize(expression: &str) -> Result<Vec<(usize, Token)>, QueryParseError> {
+    const OPS: [&str; 8] = ["!=", "<=", ">=", "==", "=", "<", ">", "~"];
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+    while let Some(&(offset, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            chars.next();
+            tokens.push((offset, if c == '(' { Token::Open } else { Token::Close }));
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => text.push(escaped),
+                        None => break
+                    },
+                    Some((_, ch)) => text.push(ch),
+                    None => return Err(QueryParseError { offset, message: String::from("unterminated string") })
+                }
+            }
+            tokens.push((offset, Token::Str(text)));
+        } else if let Some(op) = OPS.iter().find(|op| expression[offset..].starts_with(*op)) {
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push((offset, Token::Op(op)));
+        } else {
+            let mut word = String::new();
+            while let Some(&(_, ch)) = chars.peek() {
+                if ch.is_whitespace() || "()\"!=<>~".contains(ch) {
+                    break;
+                }
+                word.push(ch);
+                chars.next();
+            }
+            // A character that starts no token, such as a lone `!`
+            if word.is_empty() {
+                return Err(QueryParseError { offset, message: String::from("unexpected character") });
+            }
+            tokens.push((offset, Token::Word(word)));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some((_, Token::Word(w))) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), QueryParseError> {
+        let token = self.tokens.get(self.position).cloned().ok_or_else(|| QueryParseError {
+            offset: self.tokens.last().map(|t| t.0).unwrap_or(0),
+            message: String::from("unexpected end of expression")
+        })?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Predicate, QueryParseError> {
+        let mut any = vec![self.and()?];
+        while self.peek_keyword("or") {
+            self.position += 1;
+            any.push(self.and()?);
+        }
+        Ok(if any.len() == 1 { any.remove(0) } else { Predicate::Or(any) })
+    }
+
+    fn and(&mut self) -> Result<Predicate, QueryParseError> {
+        let mut all = vec![self.unary()?];
+        while self.peek_keyword("and") {
+            self.position += 1;
+            all.push(self.unary()?);
+        }
+        Ok(if all.len() == 1 { all.remove(0) } else { Predicate::And(all) })
+    }
+
+    fn unary(&mut self) -> Result<Predicate, QueryParseError> {
+        if self.peek_keyword("not") {
+            self.position += 1;
+            return Ok(Predicate::Not(Box::new(self.unary()?)));
+        }
+        if let Some((_, Token::Open)) = self.tokens.get(self.position) {
+            self.position += 1;
+            let inner = self.or()?;
+            return match self.next()? {
+                (_, Token::Close) => Ok(inner),
+                (offset, _) => Err(QueryParseError { offset, message: String::from("expected ')'") })
+            };
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Predicate, QueryParseError> {
+        let column = match self.next()? {
+            (_, Token::Word(word)) => match word.strip_prefix('#') {
+                Some(index) => Column::Index(index.parse().map_err(|_| QueryParseError {
+                    offset: self.tokens[self.position - 1].0, message: format!("invalid column index {:?}", word)
+                })?),
+                None => Column::Name(word)
+            },
+            (offset, _) => return Err(QueryParseError { offset, message: String::from("expected a column") })
+        };
+        let (offset, op) = match self.next()? {
+            (offset, Token::Op(op)) => (offset, op),
+            (offset, Token::Word(ref w)) if w.eq_ignore_ascii_case("contains") => (offset, "~"),
+            (offset, _) => return Err(QueryParseError { offset, message: String::from("expected an operator") })
+        };
+        let value = match self.next()? {
+            (_, Token::Str(text)) => SheetValue::String(text.as_str().into()),
+            (_, Token::Word(word)) => literal(&word),
+            (offset, _) => return Err(QueryParseError { offset, message: String::from("expected a value") })
+        };
+        if op == "~" {
+            return Ok(Predicate::Contains(column, value.to_string()));
+        }
+        if op == "=" || op == "==" || op == "!=" {
+            let eq = Predicate::Eq(column, value);
+            return Ok(if op == "!=" { Predicate::Not(Box::new(eq)) } else { eq });
+        }
+        let bound = value.as_f64().ok_or_else(|| QueryParseError { offset, message: format!("{} needs a number", op) })?;
+        Ok(match op {
+            "<" => Predicate::Range(column, Bound::Unbounded, Bound::Excluded(bound)),
+            "<=" => Predicate::Range(column, Bound::Unbounded, Bound::Included(bound)),
+            ">" => Predicate::Range(column, Bound::Excluded(bound), Bound::Unbounded),
+            _ => Predicate::Range(column, Bound::Included(bound), Bound::Unbounded)
+        })
+    }
+}
+
+/// Reads an unquoted value: a boolean, a number, or otherwise a string.
+fn literal(word: &str) -> SheetValue {
+    if word.eq_ignore_ascii_case("true") || word.eq_ignore_ascii_case("false") {
+        SheetValue::Bool(word.eq_ignore_ascii_case("true"))
+    } else if let Ok(v) = word.parse::<i32>() {
+        SheetValue::Int(v)
+    } else if let Ok(v) = word.parse::<u64>() {
+        SheetValue::PackedInts(v)
+    } else if let Ok(v) = word.parse::<f32>() {
+        SheetValue::Float(v)
+    } else {
+        SheetValue::String(word.into())
+    }
+}
+
+#[cfg(test)]
+mod query_test {
+    use super::*;
+    use super::super::test_sheet;
+    use super::super::ex::{BasicInfo, SheetDataType, StringInfo};
+
+    fn items() -> Sheet {
+        let rows = [(1, "Hi-Potion", 40u16, false), (2, "Mega-Potion", 620, true), (3, "Ether", 610, false), (4, "Elixir", 700, true)];
+        let mut sheet = test_sheet(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+            SheetDataType::UShort(BasicInfo { pointer: 4 }),
+            SheetDataType::Bool(BasicInfo { pointer: 6 }),
+        ], rows.iter().map(|(id, name, level, unique)|
+            (*id, vec![SheetValue::String((*name).into()), SheetValue::UShort(*level), SheetValue::Bool(*unique)])));
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(r#"{ "sheet": "Item", "definitions": [
+            { "name": "Name" }, { "index": 1, "name": "LevelItem" }, { "index": 2, "name": "IsUnique" } ] }"#).unwrap());
+        sheet
+    }
+
+    fn ids(query: &Query) -> Vec<usize> {
+        query.rows().unwrap().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn builder_queries() {
+        let sheet = items();
+        assert_eq!(ids(&Query::new(&sheet).filter(Predicate::range("LevelItem", 600.0..))), vec![2, 3, 4]);
+        assert_eq!(ids(&Query::new(&sheet).filter(Predicate::contains("Name", "potion"))), vec![1, 2]);
+        assert_eq!(ids(&Query::new(&sheet).filter(Predicate::eq(1, SheetValue::Int(610)))), vec![3]);
+
+        let query = Query::new(&sheet)
+            .filter(Predicate::range("LevelItem", 600.0..))
+            .sort_by("IsUnique", true)
+            .sort_by("Name", false)
+            .select(vec!["Name", "LevelItem"]);
+        assert_eq!(ids(&query), vec![4, 2, 3]);
+        assert_eq!(query.values().unwrap()[0], (4, vec![SheetValue::String("Elixir".into()), SheetValue::UShort(700)]));
+        assert!(Query::new(&sheet).filter(Predicate::eq("Missing", SheetValue::Int(1))).rows().is_err());
+    }
+
+    #[test]
+    fn expressions() {
+        let sheet = items();
+        let run = |text: &str| ids(&Query::new(&sheet).filter(Predicate::parse(text).unwrap()));
+        assert_eq!(run("LevelItem > 600"), vec![2, 3, 4]);
+        assert_eq!(run("LevelItem >= 610 and LevelItem < 700"), vec![2, 3]);
+        assert_eq!(run("Name contains \"potion\" or #2 = true"), vec![1, 2, 4]);
+        assert_eq!(run("not (Name ~ potion) and IsUnique != true"), vec![3]);
+        assert_eq!(run("Name = Ether"), vec![3]);
+
+        assert_eq!(Predicate::parse("LevelItem >").unwrap_err().message, "unexpected end of expression");
+        assert_eq!(Predicate::parse("Name > \"x\"").unwrap_err(), QueryParseError { offset: 5, message: String::from("> needs a number") });
+        assert_eq!(Predicate::parse("(Name = x").unwrap_err().message, "unexpected end of expression");
+        assert!(Predicate::parse("Name = x y").is_err());
+        assert_eq!(Predicate::parse("Name ! 3").unwrap_err(), QueryParseError { offset: 5, message: String::from("unexpected character") });
+    }
+}
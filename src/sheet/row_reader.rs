@@ -1,8 +1,9 @@
-use super::{FromSheet, SheetError, SheetDataType, SheetRow, SheetErrorType};
+use super::{FromSheet, SheetError, SheetDataType, SheetRowRef, SheetErrorType};
+use super::sestring::SeString;
 use ::byteorder::ByteOrder;
 use ::byteorder::BigEndian;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BitFlags {
     pub data: u8,
 }
@@ -23,15 +24,18 @@ impl std::fmt::Display for BitFlags {
     }
 }
 
+/// Gets `len` bytes of a row's fixed-length data, starting at `pointer`.
+fn cell_bytes<'a>(b: &SheetRowRef<'a>, pointer: u16, len: usize) -> Result<&'a [u8], SheetError> {
+    b.by.get(pointer as usize..pointer as usize + len).ok_or(SheetError { error_type: SheetErrorType::RowTooShort })
+}
+
 impl FromSheet for u32 {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::UInt(info) => {
-                    let end: usize = info.pointer as usize + 4;
-                    Ok(BigEndian::read_u32(&b.by[info.pointer as usize .. end]))
-//                    Ok(b.by[info.pointer as usize .. end].iter().enumerate().fold(0, |acc, x| acc + (((*x.1) as u32) << ((x.0) as usize) * 8)))
+                    Ok(BigEndian::read_u32(cell_bytes(b, info.pointer, 4)?))
                 },
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
@@ -42,13 +46,11 @@ impl FromSheet for u32 {
 
 impl FromSheet for i32 {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::Int(info) => {
-                    let end: usize = info.pointer as usize + 4;
-                    Ok(BigEndian::read_i32(&b.by[info.pointer as usize .. end]))
-//                    Ok(b.by[info.pointer as usize..end].iter().enumerate().fold(0, |acc, x| acc + (((*x.1) as u32) << ((x.0) as usize) * 8)) as i32)
+                    Ok(BigEndian::read_i32(cell_bytes(b, info.pointer, 4)?))
                 },
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
@@ -57,39 +59,48 @@ impl FromSheet for i32 {
     }
 }
 
+/// Gets the raw bytes of a string cell, up to (but not including) its null terminator.
+fn read_string_bytes<'a>(b: &SheetRowRef<'a>, cell: usize) -> Result<&'a [u8], SheetError> {
+    match b.types.get(cell) {
+        Some(get_result) => match get_result {
+            SheetDataType::String(info) => {
+                let sptr = BigEndian::read_u32(cell_bytes(b, info.pointer, 4)?);
+                let heap = info.strings_offset.checked_add(sptr).map(|start| start as usize)
+                    .and_then(|start| b.by.get(start..))
+                    .ok_or(SheetError { error_type: SheetErrorType::StringOutOfHeap })?;
+                let strend = heap.iter().position(|x| *x == 0).unwrap_or(heap.len());
+                Ok(&heap[..strend])
+            },
+            _ => Err(SheetError { error_type: SheetErrorType::Incompatible })
+        },
+        None => Err(SheetError{error_type: SheetErrorType::CellOutOfBounds})
+    }
+}
+
 impl FromSheet for String {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
-        match b.types.get(cell) {
-            Some(get_result) => match get_result {
-                SheetDataType::String(info) => {
-                    let end: usize = info.pointer as usize + 4;
-                    let sptr = BigEndian::read_u32(&b.by[info.pointer as usize .. end]);
-//                    let sptr = b.by[info.pointer as usize..end].iter().enumerate().fold(0, |acc, x| acc + (((*x.1) as u32) << ((x.0) as usize) * 8));
-                    let length_option = b.by[info.strings_offset as usize + sptr as usize..].iter().enumerate().filter(|x| x.1 == &0).map(|x| x.0).nth(0);
-                    let strend = match length_option {
-                        Some(val) => sptr as usize + val as usize + info.strings_offset as usize,
-                        None => b.by.len()
-                    };
-                    match Self::from_utf8((b.by[(info.strings_offset + sptr) as usize..strend]).to_vec().clone()) {
-                        Ok(val) => Ok(val),
-                        _ => Err(Self::Error { error_type: SheetErrorType::StringProcessing })
-                    }
-                },
-                _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
-            },
-            None => Err(Self::Error{error_type: SheetErrorType::CellOutOfBounds})
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
+        match Self::from_utf8(read_string_bytes(b, cell)?.to_vec()) {
+            Ok(val) => Ok(val),
+            _ => Err(Self::Error { error_type: SheetErrorType::StringProcessing })
         }
     }
 }
 
+impl FromSheet for SeString {
+    type Error = SheetError;
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
+        SeString::decode(read_string_bytes(b, cell)?)
+    }
+}
+
 impl FromSheet for u8 {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::UByte(info) => {
-                    Ok(b.by[info.pointer as usize].clone())
+                    Ok(cell_bytes(b, info.pointer, 1)?[0])
                 },
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
@@ -100,11 +111,11 @@ impl FromSheet for u8 {
 
 impl FromSheet for i8 {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::Byte(info) => {
-                    Ok(b.by[info.pointer as usize].clone() as i8)
+                    Ok(cell_bytes(b, info.pointer, 1)?[0] as i8)
                 },
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
@@ -115,12 +126,11 @@ impl FromSheet for i8 {
 
 impl FromSheet for u16 {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::UShort(info) => {
-                    let end: usize = info.pointer as usize + 2;
-                    Ok(BigEndian::read_u16(&b.by[info.pointer as usize .. end]))
+                    Ok(BigEndian::read_u16(cell_bytes(b, info.pointer, 2)?))
                 },
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
@@ -131,12 +141,26 @@ impl FromSheet for u16 {
 
 impl FromSheet for i16 {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::Short(info) => {
-                    let end: usize = info.pointer as usize + 2;
-                    Ok(BigEndian::read_i16(&b.by[info.pointer as usize .. end]))
+                    Ok(BigEndian::read_i16(cell_bytes(b, info.pointer, 2)?))
+                },
+                _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
+            },
+            None => Err(Self::Error{error_type: SheetErrorType::CellOutOfBounds})
+        }
+    }
+}
+
+impl FromSheet for u64 {
+    type Error = SheetError;
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
+        match b.types.get(cell) {
+            Some(get_result) => match get_result {
+                SheetDataType::PackedInts(info) => {
+                    Ok(BigEndian::read_u64(cell_bytes(b, info.pointer, 8)?))
                 },
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
@@ -147,11 +171,11 @@ impl FromSheet for i16 {
 
 impl FromSheet for bool {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::Bool(info) => {
-                    let b = &b.by[info.pointer as usize];
+                    let b = &cell_bytes(b, info.pointer, 1)?[0];
                     if *b == 0x00 {
                         Ok(false)
                     }
@@ -159,6 +183,7 @@ impl FromSheet for bool {
                         Ok(true)
                     }
                 },
+                SheetDataType::BitFlags(b_info) => Ok(BitFlags { data: cell_bytes(b, b_info.pointer, 1)?[0] }.get_bool(b_info.bit)),
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
             None => Err(Self::Error{error_type: SheetErrorType::CellOutOfBounds})
@@ -168,12 +193,11 @@ impl FromSheet for bool {
 
 impl FromSheet for f32 {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::Float(info) => {
-                    let end: usize = info.pointer as usize + 4;
-                    Ok(BigEndian::read_f32(&b.by[info.pointer as usize .. end]))
+                    Ok(BigEndian::read_f32(cell_bytes(b, info.pointer, 4)?))
                 },
                 _ => Err(Self::Error { error_type: SheetErrorType::Incompatible })
             },
@@ -185,11 +209,11 @@ impl FromSheet for f32 {
 
 impl FromSheet for BitFlags {
     type Error = SheetError;
-    fn from_ex_data(b: &SheetRow, cell: usize) -> Result<Self, Self::Error> {
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
         match b.types.get(cell) {
             Some(get_result) => match get_result {
                 SheetDataType::BitFlags(b_info) => {
-                    let data = b.by[b_info.pointer as usize].clone();
+                    let data = cell_bytes(b, b_info.pointer, 1)?[0];
                     let bf = BitFlags{ data };
                     Ok(bf)
                 },
@@ -198,4 +222,49 @@ impl FromSheet for BitFlags {
             None => Err(Self::Error { error_type: SheetErrorType::CellOutOfBounds })
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod row_reader_test {
+    use super::*;
+    use super::super::ex::{BasicInfo, StringInfo};
+
+    fn types() -> Vec<SheetDataType> {
+        vec![
+            SheetDataType::UInt(BasicInfo { pointer: 0 }),
+            SheetDataType::String(StringInfo { pointer: 4, strings_offset: 8 }),
+            SheetDataType::UByte(BasicInfo { pointer: 7 }),
+        ]
+    }
+
+    #[test]
+    fn truncated_row() {
+        let types = types();
+        let row = SheetRowRef { by: &[0, 0, 1, 0, 0, 0], types: &types, schema: None };
+        match row.get::<u8>(2) {
+            Err(SheetError { error_type: SheetErrorType::RowTooShort }) => (),
+            other => panic!("expected RowTooShort, got {:?}", other)
+        }
+        match row.get::<String>(1) {
+            Err(SheetError { error_type: SheetErrorType::RowTooShort }) => (),
+            other => panic!("expected RowTooShort, got {:?}", other)
+        }
+        assert_eq!(row.get::<u32>(0).unwrap(), 256);
+    }
+
+    #[test]
+    fn string_past_heap() {
+        let types = types();
+        let by = [0, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', 0];
+        assert_eq!(SheetRowRef { by: &by, types: &types, schema: None }.get::<String>(1).unwrap(), "ab");
+
+        let past = [0, 0, 0, 0, 0, 0, 0, 9, b'a', b'b', 0];
+        let overflow = [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, b'a', b'b', 0];
+        for by in [&past, &overflow] {
+            match (SheetRowRef { by, types: &types, schema: None }).get::<SeString>(1) {
+                Err(SheetError { error_type: SheetErrorType::StringOutOfHeap }) => (),
+                other => panic!("expected StringOutOfHeap, got {:?}", other)
+            }
+        }
+    }
+}
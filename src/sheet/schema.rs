@@ -0,0 +1,472 @@
+use super::ex::SheetDataType;
+use super::{SheetError, SheetErrorType};
+use ::FFXIVError;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Names, arrays and link targets for the columns of a sheet, loaded from a community
+/// schema definition (SaintCoinach `ex.json` style or EXDSchema YAML).
+#[derive(Clone, Debug)]
+pub struct SheetSchema {
+    pub name: String,
+    pub default_column: Option<String>,
+    pub columns: Vec<ColumnDefinition>,
+    pub column_order: ColumnOrder
+}
+
+#[derive(Clone, Debug)]
+pub struct ColumnDefinition {
+    pub index: usize,
+    /// Name of the column. Array elements are suffixed with their position, e.g. `BaseParam[2]`.
+    pub name: String,
+    pub link: Option<ColumnLink>
+}
+
+/// How the positions of a schema's columns relate to the columns of the EXH.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnOrder {
+    /// Positions are column indices (SaintCoinach).
+    Index,
+    /// Positions follow the columns sorted by their offset in the row (EXDSchema).
+    Offset
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColumnLink {
+    /// The column is a row id in a single sheet.
+    Sheet(String),
+    /// The column is a row id in the first of these sheets that contains it.
+    Multi(Vec<String>),
+    /// The target sheets depend on the value of another column in the same row.
+    Conditional(Vec<LinkCase>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkCase {
+    /// A column name and the value it must have for this case to apply.
+    /// A case without a condition always applies.
+    pub when: Option<(String, i64)>,
+    pub targets: Vec<String>
+}
+
+/// A column of a row, addressed either by its index or by its schema name.
+pub trait ColumnKey {
+    fn column_index(&self, schema: Option<&SheetSchema>) -> Result<usize, SheetError>;
+}
+
+impl ColumnKey for usize {
+    fn column_index(&self, _schema: Option<&SheetSchema>) -> Result<usize, SheetError> {
+        Ok(*self)
+    }
+}
+
+impl ColumnKey for &str {
+    fn column_index(&self, schema: Option<&SheetSchema>) -> Result<usize, SheetError> {
+        schema.and_then(|s| s.column_index(self))
+            .ok_or(SheetError { error_type: SheetErrorType::UnknownColumn })
+    }
+}
+
+impl ColumnKey for &String {
+    fn column_index(&self, schema: Option<&SheetSchema>) -> Result<usize, SheetError> {
+        self.as_str().column_index(schema)
+    }
+}
+
+fn schema_error(message: String) -> FFXIVError {
+    FFXIVError::DecodingSchema(Box::new(FFXIVError::Custom(message)))
+}
+
+impl SheetSchema {
+    /// Parses a single SaintCoinach sheet definition.
+    pub fn from_saint_coinach_json(json: &str) -> Result<SheetSchema, FFXIVError> {
+        let sheet: ScSheet = serde_json::from_str(json)
+            .map_err(|e| FFXIVError::DecodingSchema(Box::new(e)))?;
+        Ok(sheet.into_schema())
+    }
+
+    /// Parses a single EXDSchema sheet definition.
+    pub fn from_exdschema_yaml(yaml: &str) -> Result<SheetSchema, FFXIVError> {
+        let sheet: ExdSheet = serde_yaml::from_str(yaml)
+            .map_err(|e| FFXIVError::DecodingSchema(Box::new(e)))?;
+        Ok(sheet.into_schema())
+    }
+
+    /// Loads a definition file, choosing the format from the extension (`.json` or `.yml`/`.yaml`).
+    pub fn load(path: &Path) -> Result<SheetSchema, FFXIVError> {
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => SheetSchema::from_saint_coinach_json(&text),
+            Some("yml") | Some("yaml") => SheetSchema::from_exdschema_yaml(&text),
+            _ => Err(schema_error(format!("Unknown schema file type: {}", path.display())))
+        }
+    }
+
+    pub fn column(&self, index: usize) -> Option<&ColumnDefinition> {
+        self.columns.iter().find(|c| c.index == index)
+    }
+
+    pub fn column_name(&self, index: usize) -> Option<&str> {
+        self.column(index).map(|c| c.name.as_str())
+    }
+
+    /// Finds the index of a column by name. Names are matched case-sensitively first,
+    /// then case-insensitively.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().find(|c| c.name == name)
+            .or_else(|| self.columns.iter().find(|c| c.name.eq_ignore_ascii_case(name)))
+            .map(|c| c.index)
+    }
+
+    /// Maps the schema onto the column layout of a sheet, producing a schema whose positions
+    /// are column indices. Schemas that are already index-ordered are returned unchanged.
+    pub fn resolve(&self, types: &[SheetDataType]) -> SheetSchema {
+        let mut resolved = self.clone();
+        if self.column_order == ColumnOrder::Offset {
+            let mut by_offset: Vec<usize> = (0..types.len()).collect();
+            by_offset.sort_by_key(|i| column_sort_key(&types[*i]));
+            resolved.columns = self.columns.iter()
+                .filter(|c| c.index < by_offset.len())
+                .map(|c| ColumnDefinition { index: by_offset[c.index], ..c.clone() })
+                .collect();
+            resolved.columns.sort_by_key(|c| c.index);
+            resolved.column_order = ColumnOrder::Index;
+        }
+        resolved
+    }
+}
+
+fn column_sort_key(data_type: &SheetDataType) -> (u16, u8) {
+    match data_type {
+        SheetDataType::BitFlags(info) => (info.pointer, info.bit),
+        other => (other.get_pointer(), 0)
+    }
+}
+
+/// A collection of sheet schemas keyed by sheet name.
+#[derive(Clone, Debug, Default)]
+pub struct SchemaSet {
+    schemas: HashMap<String, SheetSchema>
+}
+
+impl SchemaSet {
+    pub fn new() -> SchemaSet {
+        SchemaSet { schemas: HashMap::new() }
+    }
+
+    /// Parses a SaintCoinach `ex.json` containing every sheet definition.
+    pub fn from_saint_coinach_ex_json(json: &str) -> Result<SchemaSet, FFXIVError> {
+        let ex: ScEx = serde_json::from_str(json)
+            .map_err(|e| FFXIVError::DecodingSchema(Box::new(e)))?;
+        let mut set = SchemaSet::new();
+        for sheet in ex.sheets {
+            set.insert(sheet.into_schema());
+        }
+        Ok(set)
+    }
+
+    /// Loads every `.json`, `.yml` and `.yaml` definition in a directory, such as
+    /// SaintCoinach's `Definitions` folder or the EXDSchema `schemas` folder.
+    pub fn load_directory(path: &Path) -> Result<SchemaSet, FFXIVError> {
+        let mut set = SchemaSet::new();
+        for entry in std::fs::read_dir(path)? {
+            let file = entry?.path();
+            match file.extension().and_then(|e| e.to_str()) {
+                Some("json") | Some("yml") | Some("yaml") => set.insert(SheetSchema::load(&file)?),
+                _ => ()
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn insert(&mut self, schema: SheetSchema) {
+        self.schemas.insert(schema.name.to_ascii_lowercase(), schema);
+    }
+
+    /// Gets the schema of a sheet. Sheet names are not case-sensitive.
+    pub fn get(&self, sheet: &str) -> Option<&SheetSchema> {
+        self.schemas.get(&sheet.to_ascii_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+}
+
+/// Assigns column positions to a flattened list of (name, link) pairs. Unnamed columns
+/// only advance the position.
+fn collect_columns(columns: &mut Vec<ColumnDefinition>, start: usize, flattened: Vec<(Option<String>, Option<ColumnLink>)>) -> usize {
+    let mut index = start;
+    for (name, link) in flattened {
+        if let Some(name) = name {
+            columns.push(ColumnDefinition { index, name, link });
+        }
+        index += 1;
+    }
+    index
+}
+
+fn array_name(base: &Option<String>, position: usize, inner: &Option<String>) -> Option<String> {
+    base.as_ref().map(|base| match inner {
+        Some(inner) if !inner.is_empty() => format!("{}[{}].{}", base, position, inner),
+        _ => format!("{}[{}]", base, position)
+    })
+}
+
+#[derive(Deserialize)]
+struct ScEx {
+    sheets: Vec<ScSheet>
+}
+
+#[derive(Deserialize)]
+struct ScSheet {
+    sheet: String,
+    #[serde(rename = "defaultColumn")]
+    default_column: Option<String>,
+    #[serde(default)]
+    definitions: Vec<ScDefinition>
+}
+
+#[derive(Deserialize)]
+struct ScDefinition {
+    index: Option<usize>,
+    name: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    count: Option<usize>,
+    definition: Option<Box<ScDefinition>>,
+    members: Option<Vec<ScDefinition>>,
+    converter: Option<ScConverter>
+}
+
+#[derive(Deserialize)]
+struct ScConverter {
+    #[serde(rename = "type")]
+    kind: String,
+    target: Option<String>,
+    targets: Option<Vec<String>>,
+    links: Option<Vec<ScComplexLink>>
+}
+
+#[derive(Deserialize)]
+struct ScComplexLink {
+    sheet: Option<String>,
+    sheets: Option<Vec<String>>,
+    when: Option<ScWhen>
+}
+
+#[derive(Deserialize)]
+struct ScWhen {
+    key: String,
+    value: i64
+}
+
+impl ScSheet {
+    fn into_schema(self) -> SheetSchema {
+        let mut columns = Vec::<ColumnDefinition>::new();
+        let mut next: usize = 0;
+        for definition in &self.definitions {
+            let start = definition.index.unwrap_or(next);
+            next = collect_columns(&mut columns, start, definition.flatten());
+        }
+        columns.sort_by_key(|c| c.index);
+        SheetSchema { name: self.sheet, default_column: self.default_column, columns, column_order: ColumnOrder::Index }
+    }
+}
+
+impl ScDefinition {
+    fn flatten(&self) -> Vec<(Option<String>, Option<ColumnLink>)> {
+        match self.kind.as_deref() {
+            Some("repeat") => {
+                let inner = self.definition.as_ref().map(|d| d.flatten()).unwrap_or_else(|| vec![(None, None)]);
+                let mut out = Vec::new();
+                for position in 0..self.count.unwrap_or(0) {
+                    for (name, link) in &inner {
+                        out.push((name.as_ref().map(|n| format!("{}[{}]", n, position)), link.clone()));
+                    }
+                }
+                out
+            },
+            Some("group") => self.members.iter().flat_map(|m| m.iter()).flat_map(|m| m.flatten()).collect(),
+            _ => vec![(self.name.clone(), self.converter.as_ref().and_then(|c| c.link()))]
+        }
+    }
+}
+
+impl ScConverter {
+    fn link(&self) -> Option<ColumnLink> {
+        match self.kind.as_str() {
+            "link" => self.target.clone().map(ColumnLink::Sheet),
+            "multiref" => self.targets.clone().map(ColumnLink::Multi),
+            "complexlink" => self.links.as_ref().map(|links| ColumnLink::Conditional(links.iter().map(|l| LinkCase {
+                when: l.when.as_ref().map(|w| (w.key.clone(), w.value)),
+                targets: l.sheets.clone().or_else(|| l.sheet.clone().map(|s| vec![s])).unwrap_or_default()
+            }).collect())),
+            _ => None
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExdSheet {
+    name: String,
+    #[serde(rename = "displayField")]
+    display_field: Option<String>,
+    #[serde(default)]
+    fields: Vec<ExdField>
+}
+
+#[derive(Deserialize)]
+struct ExdField {
+    name: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    count: Option<usize>,
+    fields: Option<Vec<ExdField>>,
+    targets: Option<Vec<String>>,
+    condition: Option<ExdCondition>
+}
+
+#[derive(Deserialize)]
+struct ExdCondition {
+    switch: String,
+    cases: BTreeMap<i64, Vec<String>>
+}
+
+impl ExdSheet {
+    fn into_schema(self) -> SheetSchema {
+        let mut columns = Vec::<ColumnDefinition>::new();
+        let flattened = self.fields.iter().flat_map(|f| f.flatten()).collect();
+        collect_columns(&mut columns, 0, flattened);
+        SheetSchema { name: self.name, default_column: self.display_field, columns, column_order: ColumnOrder::Offset }
+    }
+}
+
+impl ExdField {
+    fn flatten(&self) -> Vec<(Option<String>, Option<ColumnLink>)> {
+        match self.kind.as_deref() {
+            Some("array") => {
+                let inner: Vec<(Option<String>, Option<ColumnLink>)> = match self.fields {
+                    Some(ref fields) => fields.iter().flat_map(|f| f.flatten()).collect(),
+                    None => vec![(None, None)]
+                };
+                let mut out = Vec::new();
+                for position in 0..self.count.unwrap_or(0) {
+                    for (name, link) in &inner {
+                        out.push((array_name(&self.name, position, name), link.clone()));
+                    }
+                }
+                out
+            },
+            Some("link") => vec![(self.name.clone(), self.link())],
+            _ => vec![(self.name.clone(), None)]
+        }
+    }
+
+    fn link(&self) -> Option<ColumnLink> {
+        if let Some(ref condition) = self.condition {
+            return Some(ColumnLink::Conditional(condition.cases.iter().map(|(value, targets)| LinkCase {
+                when: Some((condition.switch.clone(), *value)),
+                targets: targets.clone()
+            }).collect()));
+        }
+        match self.targets {
+            Some(ref targets) if targets.len() == 1 => Some(ColumnLink::Sheet(targets[0].clone())),
+            Some(ref targets) => Some(ColumnLink::Multi(targets.clone())),
+            None => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod schema_test {
+    use super::*;
+    use super::super::ex::{BasicInfo, BitFlagsInfo};
+
+    #[test]
+    fn saint_coinach_definitions() {
+        let schema = SheetSchema::from_saint_coinach_json(r#"{
+            "sheet": "Item",
+            "defaultColumn": "Name",
+            "definitions": [
+                { "name": "Name" },
+                { "index": 2, "name": "ItemUICategory", "converter": { "type": "link", "target": "ItemUICategory" } },
+                { "index": 3, "type": "repeat", "count": 2, "definition": {
+                    "type": "group", "members": [ { "name": "BaseParam" }, { "name": "BaseParamValue" } ] } },
+                { "name": "AdditionalData", "converter": { "type": "complexlink", "links": [
+                    { "sheet": "GilShop", "when": { "key": "FilterGroup", "value": 14 } },
+                    { "sheets": ["Orchestrion", "Mount"] } ] } }
+            ]
+        }"#).unwrap();
+
+        assert_eq!(schema.column_index("Name"), Some(0));
+        assert_eq!(schema.column_index("itemuicategory"), Some(2));
+        assert_eq!(schema.column_name(5), Some("BaseParam[1]"));
+        assert_eq!(schema.column_name(6), Some("BaseParamValue[1]"));
+        assert_eq!(schema.column(2).unwrap().link, Some(ColumnLink::Sheet("ItemUICategory".into())));
+        match schema.column(7).unwrap().link {
+            Some(ColumnLink::Conditional(ref cases)) => {
+                assert_eq!(cases[0].when, Some(("FilterGroup".into(), 14)));
+                assert_eq!(cases[1].targets, vec![String::from("Orchestrion"), String::from("Mount")]);
+            },
+            _ => panic!("expected a conditional link")
+        }
+    }
+
+    #[test]
+    fn exdschema_offset_order() {
+        let schema = SheetSchema::from_exdschema_yaml("
+name: Item
+displayField: Name
+fields:
+  - name: Name
+  - name: Flags
+    type: array
+    count: 2
+  - name: Category
+    type: link
+    targets: [ItemUICategory, ItemSearchCategory]
+").unwrap();
+        assert_eq!(schema.column_order, ColumnOrder::Offset);
+        assert_eq!(schema.column_name(2), Some("Flags[1]"));
+
+        // Sorted by offset, column 3 (offset 0) is Name, columns 2 and 1 (offset 4, bits 0
+        // and 1) are the flags, and column 0 (offset 8) is Category
+        let types = vec![
+            SheetDataType::UInt(BasicInfo { pointer: 8 }),
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 4, bit: 1 }),
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 4, bit: 0 }),
+            SheetDataType::UShort(BasicInfo { pointer: 0 }),
+        ];
+        let resolved = schema.resolve(&types);
+        assert_eq!(resolved.column_index("Category"), Some(0));
+        assert_eq!(resolved.column_index("Name"), Some(3));
+        assert_eq!(resolved.column_index("Flags[0]"), Some(2));
+        assert_eq!(resolved.column_index("Flags[1]"), Some(1));
+        assert_eq!(resolved.column(0).unwrap().link,
+            Some(ColumnLink::Multi(vec![String::from("ItemUICategory"), String::from("ItemSearchCategory")])));
+    }
+
+    #[test]
+    fn row_access_by_name() {
+        use super::super::{Sheet, SheetRow};
+        use std::rc::Rc;
+
+        let types = Rc::new(vec![SheetDataType::UInt(BasicInfo { pointer: 0 })]);
+        let mut sheet = Sheet { rows: ::indexmap::IndexMap::new(), types: types.clone(), column_count: 1, schema: None };
+        sheet.rows.insert(1, SheetRow { by: vec![0, 0, 0x01, 0x02], types: types.clone(), schema: None });
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(
+            r#"{ "sheet": "Item", "definitions": [ { "name": "ItemSearchCategory" } ] }"#).unwrap());
+
+        let row = &sheet.rows[&1];
+        assert_eq!(row.get::<u32>("ItemSearchCategory").unwrap(), 0x0102);
+        assert_eq!(row.get::<u32>(0).unwrap(), 0x0102);
+        assert!(row.get::<u32>("Missing").is_err());
+        assert_eq!(sheet.column_name(0), Some("ItemSearchCategory"));
+    }
+}
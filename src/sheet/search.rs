@@ -0,0 +1,289 @@
+use super::{Sheet, SheetError};
+use super::ex::{SheetDataType, SheetLanguage};
+use super::sestring::SeString;
+
+use std::collections::HashMap;
+
+/// Characters of context kept on each side of a match in a snippet.
+const SNIPPET_CONTEXT: usize = 24;
+
+/// A string cell matching a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub sheet: String,
+    pub row: usize,
+    pub column: usize,
+    pub language: SheetLanguage,
+    /// The text around the first match, with `…` where it was cut.
+    pub snippet: String
+}
+
+struct Entry {
+    sheet: usize,
+    row: usize,
+    column: usize,
+    language: SheetLanguage,
+    text: String
+}
+
+/// An in-memory inverted index over the string cells of sheets. Text is indexed without
+/// its macro payloads.
+#[derive(Default)]
+pub struct SearchIndex {
+    sheets: Vec<String>,
+    entries: Vec<Entry>,
+    postings: HashMap<String, Vec<usize>>
+}
+
+/// Kana, CJK ideographs and Hangul, which are written without spaces between words.
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3040..=0x30FF | 0x31F0..=0x31FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+        | 0xAC00..=0xD7AF | 0xF900..=0xFAFF | 0xFF66..=0xFF9F)
+}
+
+/// Lowercases text one character at a time, so character positions are kept.
+fn normalize(text: &str) -> String {
+    text.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect()
+}
+
+/// Splits text into search tokens. Latin and other spaced scripts give one lowercase token
+/// per word. Runs of CJK characters give every single character and every pair of
+/// adjacent characters, so any part of a sentence can be found without a dictionary.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut previous_cjk: Option<char> = None;
+    for c in normalize(text).chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            tokens.push(c.to_string());
+            if let Some(p) = previous_cjk {
+                tokens.push([p, c].iter().collect());
+            }
+            previous_cjk = Some(c);
+            continue;
+        }
+        previous_cjk = None;
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// The runs of CJK characters in normalized text, which must appear unbroken in a match.
+fn cjk_runs(text: &str) -> Vec<String> {
+    let mut runs = Vec::new();
+    let mut run = String::new();
+    for c in text.chars() {
+        if is_cjk(c) {
+            run.push(c);
+        } else if !run.is_empty() {
+            runs.push(std::mem::take(&mut run));
+        }
+    }
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+/// Cuts the text around the character range `start..end`.
+fn snippet(text: &str, start: usize, end: usize) -> String {
+    let chars: Vec<char> = text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    let from = start.saturating_sub(SNIPPET_CONTEXT);
+    let to = (end + SNIPPET_CONTEXT).min(chars.len());
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(&chars[from..to]);
+    if to < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// The entries for every non-empty string cell of a sheet in one language.
+fn string_entries(sheet_id: usize, language: SheetLanguage, sheet: &Sheet) -> Result<Vec<Entry>, SheetError> {
+    let columns: Vec<usize> = sheet.types.iter().enumerate()
+        .filter(|(_, t)| matches!(t, SheetDataType::String(_)))
+        .map(|(column, _)| column)
+        .collect();
+    let mut entries = Vec::new();
+    for (row, data) in sheet.rows.iter() {
+        for column in &columns {
+            let text = data.get::<SeString>(*column)?.text();
+            if !text.is_empty() {
+                entries.push(Entry { sheet: sheet_id, row: *row, column: *column, language, text });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+impl SearchIndex {
+    pub fn new() -> SearchIndex {
+        SearchIndex::default()
+    }
+
+    /// The number of indexed cells.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Indexes every non-empty string cell of a sheet in one language. If a cell can't be
+    /// read, nothing from the sheet is indexed.
+    pub fn add_sheet(&mut self, name: &str, language: SheetLanguage, sheet: &Sheet) -> Result<(), SheetError> {
+        let entries = string_entries(self.sheets.len(), language, sheet)?;
+        self.insert(name, entries);
+        Ok(())
+    }
+
+    /// Indexes a sheet in several languages, as read by `FFXIV::build_search_index`. If a
+    /// cell can't be read in any of them, nothing from the sheet is indexed.
+    pub fn add_sheets(&mut self, name: &str, sheets: &[(SheetLanguage, Sheet)]) -> Result<(), SheetError> {
+        let mut entries = Vec::new();
+        for (language, sheet) in sheets {
+            entries.extend(string_entries(self.sheets.len(), *language, sheet)?);
+        }
+        self.insert(name, entries);
+        Ok(())
+    }
+
+    fn insert(&mut self, name: &str, entries: Vec<Entry>) {
+        self.sheets.push(String::from(name));
+        for entry in entries {
+            let mut tokens = tokenize(&entry.text);
+            tokens.sort();
+            tokens.dedup();
+            for token in tokens {
+                self.postings.entry(token).or_default().push(self.entries.len());
+            }
+            self.entries.push(entry);
+        }
+    }
+
+    /// Finds the cells containing every word of the query. CJK text in the query must
+    /// appear unbroken in the cell. Hits are in the order the sheets were added.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let mut tokens = tokenize(query);
+        tokens.sort();
+        tokens.dedup();
+        let mut lists = Vec::with_capacity(tokens.len());
+        for token in &tokens {
+            match self.postings.get(token) {
+                Some(list) => lists.push(list),
+                None => return Vec::new()
+            }
+        }
+        lists.sort_by_key(|list| list.len());
+        let (first, rest) = match lists.split_first() {
+            Some(split) => split,
+            None => return Vec::new()
+        };
+
+        let query = normalize(query);
+        let runs = cjk_runs(&query);
+        // Snippets are centred on the longest part of the query
+        let anchor = runs.iter().chain(tokens.iter()).max_by_key(|t| t.chars().count()).cloned().unwrap_or_default();
+        first.iter()
+            .filter(|entry| rest.iter().all(|list| list.binary_search(entry).is_ok()))
+            .filter_map(|entry| {
+                let entry = &self.entries[*entry];
+                let text = normalize(&entry.text);
+                if !runs.iter().all(|run| text.contains(run.as_str())) {
+                    return None;
+                }
+                let start = text.find(anchor.as_str()).map(|b| text[..b].chars().count()).unwrap_or(0);
+                Some(SearchHit {
+                    sheet: self.sheets[entry.sheet].clone(),
+                    row: entry.row,
+                    column: entry.column,
+                    language: entry.language,
+                    snippet: snippet(&entry.text, start, start + anchor.chars().count())
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod search_test {
+    use super::*;
+    use super::super::{SheetValue, test_sheet};
+    use super::super::ex::{BasicInfo, StringInfo};
+
+    fn sheet(rows: &[(usize, &str)]) -> Sheet {
+        test_sheet(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+            SheetDataType::UInt(BasicInfo { pointer: 4 }),
+        ], rows.iter().map(|(id, text)| (*id, vec![SheetValue::String((*text).into())])))
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("Hi-Potion, x3"), vec!["hi", "potion", "x3"]);
+        assert_eq!(tokenize("ポーション"), vec!["ポ", "ー", "ポー", "シ", "ーシ", "ョ", "ショ", "ン", "ョン"]);
+        assert_eq!(tokenize("HQの薬"), vec!["hq", "の", "薬", "の薬"]);
+    }
+
+    #[test]
+    fn latin_and_japanese() {
+        let mut index = SearchIndex::new();
+        index.add_sheet("Item", SheetLanguage::English, &sheet(&[(1, "Potion"), (2, "Hi-Potion"), (3, "Ether")])).unwrap();
+        index.add_sheet("Item", SheetLanguage::Japanese, &sheet(&[(1, "ポーション"), (2, "ハイポーション"), (3, "エーテル")])).unwrap();
+        assert_eq!(index.len(), 6);
+
+        let rows = |query: &str| index.search(query).iter().map(|h| (h.row, h.language)).collect::<Vec<_>>();
+        assert_eq!(rows("potion"), vec![(1, SheetLanguage::English), (2, SheetLanguage::English)]);
+        assert_eq!(rows("HI potion"), vec![(2, SheetLanguage::English)]);
+        assert_eq!(rows("pot"), vec![]);
+        assert_eq!(rows("ポーション"), vec![(1, SheetLanguage::Japanese), (2, SheetLanguage::Japanese)]);
+        assert_eq!(rows("ハイ"), vec![(2, SheetLanguage::Japanese)]);
+        assert_eq!(rows("ションハイ"), vec![]);
+        assert_eq!(rows(""), vec![]);
+
+        // Every token of ハイハ is in the cell, but not as one run
+        let mut split = SearchIndex::new();
+        split.add_sheet("Item", SheetLanguage::Japanese, &sheet(&[(1, "ハイ イハ")])).unwrap();
+        assert_eq!(split.search("ハイハ"), vec![]);
+        assert_eq!(split.search("イハ").len(), 1);
+
+        // A sheet with an unreadable cell leaves the index as it was
+        let mut broken = sheet(&[(1, "Elixir"), (2, "Megalixir")]);
+        broken.rows.get_mut(&2).unwrap().by[3] = 0xff;
+        assert!(split.add_sheet("Item", SheetLanguage::English, &broken).is_err());
+        assert_eq!(split.len(), 1);
+        assert_eq!(split.search("elixir"), vec![]);
+        // As does one that can only be read in some of its languages
+        let sheets = [(SheetLanguage::English, sheet(&[(1, "Elixir")])), (SheetLanguage::German, broken)];
+        assert!(split.add_sheets("Item", &sheets).is_err());
+        assert_eq!(split.len(), 1);
+        assert_eq!(split.search("elixir"), vec![]);
+
+        let hit = &index.search("テル")[0];
+        assert_eq!((hit.sheet.as_str(), hit.row, hit.column), ("Item", 3, 0));
+        assert_eq!(hit.snippet, "エーテル");
+    }
+
+    #[test]
+    fn snippets() {
+        let text = "The quick brown fox jumps over the lazy dog and keeps running far away";
+        let mut index = SearchIndex::new();
+        index.add_sheet("Quest", SheetLanguage::English, &sheet(&[(7, text)])).unwrap();
+        assert_eq!(index.search("lazy")[0].snippet, "…rown fox jumps over the lazy dog and keeps running f…");
+        assert_eq!(index.search("quick")[0].snippet, "The quick brown fox jumps over th…");
+    }
+}
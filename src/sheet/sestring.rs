@@ -0,0 +1,473 @@
+use super::{SheetError, SheetErrorType};
+
+/// Marks the start of a macro payload within an encoded SeString
+const PAYLOAD_START: u8 = 0x02;
+/// Marks the end of a macro payload within an encoded SeString
+const PAYLOAD_END: u8 = 0x03;
+
+/// Integers below this value are stored in a single byte as `value + 1`
+const SINGLE_BYTE_LIMIT: u32 = 0xCF;
+
+const PACKED_INTEGER_START: u8 = 0xF0;
+const PACKED_INTEGER_END: u8 = 0xFE;
+const STRING_EXPRESSION: u8 = 0xFF;
+
+/// A game string as stored in the string columns of a sheet. Game strings are a mix of
+/// UTF-8 text and macro payloads (colors, line breaks, conditionals, etc.), which are
+/// kept as a tree so they can be inspected, edited and written back.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub struct SeString {
+    pub payloads: Vec<Payload>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Payload {
+    Text(String),
+    Macro(Macro),
+    /// A macro whose body could not be decoded into expressions, or whose encoding
+    /// differs from the canonical one. The body is kept verbatim so it survives a round trip.
+    RawMacro(u8, Vec<u8>)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Macro {
+    pub code: u8,
+    pub args: Vec<Expression>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expression {
+    /// An integer that is written in its shortest form.
+    Integer(u32),
+    /// An integer written with an explicit 0xF0-0xFE marker. The low nibble of the marker
+    /// (plus one) is a mask of which of the four bytes of the value are present, most
+    /// significant first. Only bytes selected by the mask are written.
+    PackedInteger(u8, u32),
+    /// A value supplied by the game at runtime, such as the current hour (0xD0-0xDF, 0xEC).
+    Placeholder(u8),
+    Comparison(ComparisonOperator, Box<Expression>, Box<Expression>),
+    Parameter(ParameterKind, Box<Expression>),
+    String(SeString)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ComparisonOperator {
+    GreaterThanOrEqual,
+    GreaterThan,
+    LessThanOrEqual,
+    LessThan,
+    Equal,
+    NotEqual
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParameterKind {
+    Integer,
+    Player,
+    String,
+    Object
+}
+
+impl ComparisonOperator {
+    fn from_code(code: u8) -> Option<ComparisonOperator> {
+        match code {
+            0xE0 => Some(ComparisonOperator::GreaterThanOrEqual),
+            0xE1 => Some(ComparisonOperator::GreaterThan),
+            0xE2 => Some(ComparisonOperator::LessThanOrEqual),
+            0xE3 => Some(ComparisonOperator::LessThan),
+            0xE4 => Some(ComparisonOperator::Equal),
+            0xE5 => Some(ComparisonOperator::NotEqual),
+            _ => None
+        }
+    }
+
+    pub fn get_code(&self) -> u8 {
+        match self {
+            ComparisonOperator::GreaterThanOrEqual => 0xE0,
+            ComparisonOperator::GreaterThan => 0xE1,
+            ComparisonOperator::LessThanOrEqual => 0xE2,
+            ComparisonOperator::LessThan => 0xE3,
+            ComparisonOperator::Equal => 0xE4,
+            ComparisonOperator::NotEqual => 0xE5,
+        }
+    }
+}
+
+impl ParameterKind {
+    fn from_code(code: u8) -> Option<ParameterKind> {
+        match code {
+            0xE8 => Some(ParameterKind::Integer),
+            0xE9 => Some(ParameterKind::Player),
+            0xEA => Some(ParameterKind::String),
+            0xEB => Some(ParameterKind::Object),
+            _ => None
+        }
+    }
+
+    pub fn get_code(&self) -> u8 {
+        match self {
+            ParameterKind::Integer => 0xE8,
+            ParameterKind::Player => 0xE9,
+            ParameterKind::String => 0xEA,
+            ParameterKind::Object => 0xEB,
+        }
+    }
+}
+
+fn malformed() -> SheetError {
+    SheetError { error_type: SheetErrorType::MalformedPayload }
+}
+
+impl SeString {
+    /// Decodes the raw bytes of a string cell (without the null terminator).
+    pub fn decode(data: &[u8]) -> Result<SeString, SheetError> {
+        let mut payloads = Vec::<Payload>::new();
+        let mut pos: usize = 0;
+        while pos < data.len() {
+            if data[pos] == PAYLOAD_START {
+                let (payload, next) = decode_macro(data, pos)?;
+                payloads.push(payload);
+                pos = next;
+            } else {
+                let end = data[pos..].iter().position(|b| *b == PAYLOAD_START)
+                    .map(|p| pos + p)
+                    .unwrap_or(data.len());
+                let text = String::from_utf8(data[pos..end].to_vec())
+                    .map_err(|_| SheetError { error_type: SheetErrorType::StringProcessing })?;
+                payloads.push(Payload::Text(text));
+                pos = end;
+            }
+        }
+        Ok(SeString { payloads })
+    }
+
+    /// Encodes the payload tree back into the byte form used by string cells.
+    /// Encoding a decoded SeString yields the exact bytes it was decoded from.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::<u8>::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        for payload in &self.payloads {
+            match payload {
+                Payload::Text(text) => out.extend_from_slice(text.as_bytes()),
+                Payload::Macro(m) => {
+                    let mut body = Vec::<u8>::new();
+                    for arg in &m.args {
+                        arg.encode_into(&mut body);
+                    }
+                    write_macro(out, m.code, &body);
+                },
+                Payload::RawMacro(code, body) => write_macro(out, *code, body),
+            }
+        }
+    }
+
+    /// Gets the text of the string with all macro payloads removed.
+    pub fn text(&self) -> String {
+        self.payloads.iter().filter_map(|p| match p {
+            Payload::Text(text) => Some(text.as_str()),
+            _ => None
+        }).collect()
+    }
+}
+
+impl<'a> From<&'a str> for SeString {
+    fn from(text: &'a str) -> SeString {
+        SeString { payloads: vec![Payload::Text(String::from(text))] }
+    }
+}
+
+/// Writes the text of the string, with each macro payload written as a `<hex:..>` tag
+/// holding its encoded bytes. A literal `<` is written as `<hex:3C>`, so text can't be
+/// mistaken for a tag when parsed back.
+impl std::fmt::Display for SeString {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for payload in &self.payloads {
+            match payload {
+                Payload::Text(text) => write!(f, "{}", text.replace('<', "<hex:3C>"))?,
+                _ => {
+                    write!(f, "<hex:")?;
+                    for byte in (SeString { payloads: vec![payload.clone()] }).encode() {
+                        write!(f, "{:02X}", byte)?;
+                    }
+                    write!(f, ">")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the display form of a string, where macro payloads are written as `<hex:..>` tags.
+impl std::str::FromStr for SeString {
+    type Err = SheetError;
+    fn from_str(text: &str) -> Result<SeString, SheetError> {
+        let mut bytes = Vec::<u8>::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("<hex:") {
+            bytes.extend_from_slice(&rest.as_bytes()[..start]);
+            let tag = &rest[start + 5..];
+            let end = tag.find('>').ok_or_else(malformed)?;
+            let hex = &tag.as_bytes()[..end];
+            if !hex.len().is_multiple_of(2) {
+                return Err(malformed());
+            }
+            for pair in hex.chunks(2) {
+                let digits = std::str::from_utf8(pair).map_err(|_| malformed())?;
+                bytes.push(u8::from_str_radix(digits, 16).map_err(|_| malformed())?);
+            }
+            rest = &tag[end + 1..];
+        }
+        bytes.extend_from_slice(rest.as_bytes());
+        SeString::decode(&bytes)
+    }
+}
+
+/// Strings are serialized in their display form.
+impl serde::Serialize for SeString {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SeString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<SeString, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Expression {
+    /// Encodes the expression using the 0xD0-0xFF expression prefixes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::<u8>::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Expression::Integer(value) => write_integer(out, *value),
+            Expression::PackedInteger(marker, value) => write_packed_integer(out, *marker, *value),
+            Expression::Placeholder(code) => out.push(*code),
+            Expression::Comparison(op, left, right) => {
+                out.push(op.get_code());
+                left.encode_into(out);
+                right.encode_into(out);
+            },
+            Expression::Parameter(kind, inner) => {
+                out.push(kind.get_code());
+                inner.encode_into(out);
+            },
+            Expression::String(s) => {
+                let bytes = s.encode();
+                out.push(STRING_EXPRESSION);
+                write_integer(out, bytes.len() as u32);
+                out.extend_from_slice(&bytes);
+            }
+        }
+    }
+
+    fn decode(data: &[u8], pos: usize) -> Result<(Expression, usize), SheetError> {
+        let code = *data.get(pos).ok_or_else(malformed)?;
+        match code {
+            0x01..=0xCF => Ok((Expression::Integer(code as u32 - 1), pos + 1)),
+            0xD0..=0xDF | 0xEC => Ok((Expression::Placeholder(code), pos + 1)),
+            0xE0..=0xE5 => {
+                let op = ComparisonOperator::from_code(code).ok_or_else(malformed)?;
+                let (left, next) = Expression::decode(data, pos + 1)?;
+                let (right, next) = Expression::decode(data, next)?;
+                Ok((Expression::Comparison(op, Box::new(left), Box::new(right)), next))
+            },
+            0xE8..=0xEB => {
+                let kind = ParameterKind::from_code(code).ok_or_else(malformed)?;
+                let (inner, next) = Expression::decode(data, pos + 1)?;
+                Ok((Expression::Parameter(kind, Box::new(inner)), next))
+            },
+            PACKED_INTEGER_START..=PACKED_INTEGER_END => {
+                let (value, next) = read_packed_integer(data, pos)?;
+                if value >= SINGLE_BYTE_LIMIT && packed_marker(value) == code {
+                    Ok((Expression::Integer(value), next))
+                } else {
+                    Ok((Expression::PackedInteger(code, value), next))
+                }
+            },
+            STRING_EXPRESSION => {
+                let (length, start) = read_integer(data, pos + 1)?;
+                let end = start + length as usize;
+                if end > data.len() {
+                    return Err(malformed());
+                }
+                Ok((Expression::String(SeString::decode(&data[start..end])?), end))
+            },
+            _ => Err(malformed())
+        }
+    }
+}
+
+/// Decodes the macro payload starting at `pos`, returning it and the position after its end marker.
+fn decode_macro(data: &[u8], pos: usize) -> Result<(Payload, usize), SheetError> {
+    let code = *data.get(pos + 1).ok_or_else(malformed)?;
+    let (length, body_start) = read_integer(data, pos + 2)?;
+    let body_end = body_start + length as usize;
+    if body_end >= data.len() || data[body_end] != PAYLOAD_END {
+        return Err(malformed());
+    }
+    let body = &data[body_start..body_end];
+    let original = &data[pos..body_end + 1];
+
+    let parsed = decode_macro_args(body).map(|args| Macro { code, args });
+    let payload = match parsed {
+        Ok(m) => {
+            let candidate = Payload::Macro(m);
+            let reencoded = SeString { payloads: vec![candidate.clone()] }.encode();
+            if reencoded.as_slice() == original { candidate } else { Payload::RawMacro(code, body.to_vec()) }
+        },
+        Err(_) => Payload::RawMacro(code, body.to_vec())
+    };
+    Ok((payload, body_end + 1))
+}
+
+fn decode_macro_args(body: &[u8]) -> Result<Vec<Expression>, SheetError> {
+    let mut args = Vec::<Expression>::new();
+    let mut pos: usize = 0;
+    while pos < body.len() {
+        let (arg, next) = Expression::decode(body, pos)?;
+        args.push(arg);
+        pos = next;
+    }
+    Ok(args)
+}
+
+fn write_macro(out: &mut Vec<u8>, code: u8, body: &[u8]) {
+    out.push(PAYLOAD_START);
+    out.push(code);
+    write_integer(out, body.len() as u32);
+    out.extend_from_slice(body);
+    out.push(PAYLOAD_END);
+}
+
+/// Gets the 0xF0-0xFE marker that covers exactly the non-zero bytes of `value`.
+fn packed_marker(value: u32) -> u8 {
+    let mut mask: u8 = 0;
+    for i in 0..4 {
+        if (value >> (8 * (3 - i))) & 0xFF != 0 {
+            mask |= 0x8 >> i;
+        }
+    }
+    PACKED_INTEGER_START + mask.max(1) - 1
+}
+
+fn write_integer(out: &mut Vec<u8>, value: u32) {
+    if value < SINGLE_BYTE_LIMIT {
+        out.push(value as u8 + 1);
+    } else {
+        write_packed_integer(out, packed_marker(value), value);
+    }
+}
+
+fn write_packed_integer(out: &mut Vec<u8>, marker: u8, value: u32) {
+    let mask = marker.wrapping_sub(PACKED_INTEGER_START) + 1;
+    out.push(marker);
+    for i in 0..4 {
+        if mask & (0x8 >> i) != 0 {
+            out.push((value >> (8 * (3 - i))) as u8);
+        }
+    }
+}
+
+fn read_integer(data: &[u8], pos: usize) -> Result<(u32, usize), SheetError> {
+    let code = *data.get(pos).ok_or_else(malformed)?;
+    match code {
+        0x01..=0xCF => Ok((code as u32 - 1, pos + 1)),
+        PACKED_INTEGER_START..=PACKED_INTEGER_END => read_packed_integer(data, pos),
+        _ => Err(malformed())
+    }
+}
+
+fn read_packed_integer(data: &[u8], pos: usize) -> Result<(u32, usize), SheetError> {
+    let mask = data[pos] - PACKED_INTEGER_START + 1;
+    let mut value: u32 = 0;
+    let mut next = pos + 1;
+    for i in 0..4 {
+        if mask & (0x8 >> i) != 0 {
+            let byte = *data.get(next).ok_or_else(malformed)?;
+            value |= (byte as u32) << (8 * (3 - i));
+            next += 1;
+        }
+    }
+    Ok((value, next))
+}
+
+#[cfg(test)]
+mod sestring_test {
+    use super::*;
+
+    #[test]
+    fn integer_encodings() {
+        assert_eq!(Expression::Integer(0).encode(), vec![0x01]);
+        assert_eq!(Expression::Integer(0xCE).encode(), vec![0xCF]);
+        assert_eq!(Expression::Integer(0xCF).encode(), vec![0xF0, 0xCF]);
+        assert_eq!(Expression::Integer(0x1200).encode(), vec![0xF1, 0x12]);
+        assert_eq!(Expression::Integer(0x1234).encode(), vec![0xF2, 0x12, 0x34]);
+        assert_eq!(Expression::Integer(0x12345678).encode(), vec![0xFE, 0x12, 0x34, 0x56, 0x78]);
+    }
+
+    #[test]
+    fn round_trip_is_lossless() {
+        let data: Vec<u8> = vec![
+            b'H', b'i', b' ',
+            // color macro with a packed integer
+            0x02, 0x48, 0x04, 0xF2, 0x02, 0x25, 0x03,
+            b'x',
+            // if macro: (PlayerParameter(4) == 1) ? "a" : "bc"
+            0x02, 0x08, 0x0C, 0xE4, 0xE9, 0x05, 0x02, 0xFF, 0x02, b'a', 0xFF, 0x03, b'b', b'c', 0x03,
+            // non-canonical packed integer (0xF2 with a zero low byte)
+            0x02, 0x13, 0x04, 0xF2, 0x01, 0x00, 0x03,
+            // newline macro with no arguments
+            0x02, 0x10, 0x01, 0x03,
+        ];
+        let decoded = SeString::decode(&data).unwrap();
+        assert_eq!(decoded.text(), "Hi x");
+        assert_eq!(decoded.payloads[3], Payload::Macro(Macro { code: 0x08, args: vec![
+            Expression::Comparison(ComparisonOperator::Equal,
+                Box::new(Expression::Parameter(ParameterKind::Player, Box::new(Expression::Integer(4)))),
+                Box::new(Expression::Integer(1))),
+            Expression::String(SeString::from("a")),
+            Expression::String(SeString::from("bc")),
+        ]}));
+        assert_eq!(decoded.payloads[4], Payload::Macro(Macro { code: 0x13, args: vec![
+            Expression::PackedInteger(0xF2, 0x0100)
+        ]}));
+        assert_eq!(decoded.encode(), data);
+    }
+
+    #[test]
+    fn unknown_macro_body_is_kept_raw() {
+        let data: Vec<u8> = vec![0x02, 0x20, 0x03, 0x00, 0xEE, 0x03];
+        let decoded = SeString::decode(&data).unwrap();
+        assert_eq!(decoded.payloads, vec![Payload::RawMacro(0x20, vec![0x00, 0xEE])]);
+        assert_eq!(decoded.encode(), data);
+    }
+
+    #[test]
+    fn display_uses_hex_tags() {
+        let decoded = SeString::decode(&[b'a', 0x02, 0x10, 0x01, 0x03, b'b']).unwrap();
+        assert_eq!(format!("{}", decoded), "a<hex:02100103>b");
+        assert_eq!("a<hex:02100103>b".parse::<SeString>().unwrap(), decoded);
+        assert!("a<hex:021001>b".parse::<SeString>().is_err());
+
+        // Literal text that looks like a tag survives the round trip
+        let literal = SeString { payloads: vec![Payload::Text(String::from("1 < 2 <hex:02>")), decoded.payloads[1].clone()] };
+        assert_eq!(format!("{}", literal), "1 <hex:3C> 2 <hex:3C>hex:02><hex:02100103>");
+        assert_eq!(literal.to_string().parse::<SeString>().unwrap(), literal);
+    }
+
+    #[test]
+    fn truncated_macro_is_an_error() {
+        assert!(SeString::decode(&[b'a', 0x02, 0x10, 0x05, 0x03]).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use super::{Sheet, SheetValue};
+use super::ex::{SheetDataType, SheetLanguage};
+use super::schema::SheetSchema;
+use ::FFXIVError;
+
+use rusqlite::Connection;
+use rusqlite::types::Value;
+
+fn database_error(e: rusqlite::Error) -> FFXIVError {
+    FFXIVError::ExportingDatabase(Box::new(e))
+}
+
+/// Quotes a table or column name for SQL.
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// The table name used for a sheet. Folders in the name are joined with `_`, so
+/// `quest/001/ClsHyu001_00194` becomes `quest_001_ClsHyu001_00194`.
+pub fn table_name(sheet: &str) -> String {
+    sheet.replace('/', "_")
+}
+
+/// The SQL column names of a sheet: schema names where there are any, `c<index>`
+/// otherwise. Names that would clash get their index appended.
+pub fn column_names(types: &[SheetDataType], schema: Option<&SheetSchema>) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(types.len());
+    for cell in 0..types.len() {
+        let name = schema.and_then(|s| s.column_name(cell)).map(String::from).unwrap_or_else(|| format!("c{}", cell));
+        let taken = |n: &str| n.eq_ignore_ascii_case("id") || n.eq_ignore_ascii_case("language")
+            || names.iter().any(|other| other.eq_ignore_ascii_case(n));
+        names.push(if taken(&name) { format!("{}_{}", name, cell) } else { name });
+    }
+    names
+}
+
+fn sql_type(data_type: &SheetDataType) -> &'static str {
+    match data_type {
+        SheetDataType::String(_) => "TEXT",
+        SheetDataType::Float(_) => "REAL",
+        _ => "INTEGER"
+    }
+}
+
+fn sql_value(value: SheetValue) -> Value {
+    match value {
+        SheetValue::String(v) => Value::Text(v.to_string()),
+        SheetValue::Float(v) => Value::Real(v as f64),
+        SheetValue::PackedInts(v) => Value::Integer(v as i64),
+        other => Value::Integer(other.as_i64().unwrap_or_default())
+    }
+}
+
+/// Creates the table of a sheet, replacing any existing one. Each table has an `id` and a
+/// `language` column (the language code, or empty for sheets without languages) ahead of
+/// the sheet's own columns. Columns the schema marks as links are indexed.
+pub fn create_table(conn: &Connection, sheet: &str, types: &[SheetDataType], schema: Option<&SheetSchema>) -> Result<(), FFXIVError> {
+    let table = table_name(sheet);
+    let names = column_names(types, schema);
+    let mut columns = vec![String::from("\"id\" INTEGER NOT NULL"), String::from("\"language\" TEXT NOT NULL")];
+    columns.extend(names.iter().zip(types).map(|(name, t)| format!("{} {}", quote(name), sql_type(t))));
+    columns.push(String::from("PRIMARY KEY (\"id\", \"language\")"));
+    conn.execute_batch(&format!("DROP TABLE IF EXISTS {0}; CREATE TABLE {0} ({1});", quote(&table), columns.join(", ")))
+        .map_err(database_error)?;
+
+    if let Some(schema) = schema {
+        for (cell, name) in names.iter().enumerate() {
+            if schema.column(cell).and_then(|c| c.link.as_ref()).is_some() {
+                conn.execute_batch(&format!("CREATE INDEX {} ON {} ({});",
+                    quote(&format!("{}_{}", table, name)), quote(&table), quote(name))).map_err(database_error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Inserts the rows of a sheet in one language into its table.
+pub fn insert_rows(conn: &Connection, sheet_name: &str, sheet: &Sheet, language: SheetLanguage) -> Result<(), FFXIVError> {
+    let placeholders = vec!["?"; sheet.types.len() + 2].join(", ");
+    let mut statement = conn.prepare(&format!("INSERT INTO {} VALUES ({})", quote(&table_name(sheet_name)), placeholders))
+        .map_err(database_error)?;
+    let code = language.get_language_code().unwrap_or_default();
+    for (id, row) in sheet.rows.iter() {
+        let mut values = vec![Value::Integer(*id as i64), Value::Text(code.clone())];
+        values.extend(row.values()?.into_iter().map(sql_value));
+        statement.execute(rusqlite::params_from_iter(values)).map_err(database_error)?;
+    }
+    Ok(())
+}
+
+/// Creates the table of a sheet and inserts its rows in every given language, in one
+/// transaction. The sheets must share a layout; the schema of the first one is used.
+pub fn export_sheet(conn: &mut Connection, sheet_name: &str, sheets: &[(SheetLanguage, Sheet)]) -> Result<(), FFXIVError> {
+    let first = match sheets.first() {
+        Some((_, sheet)) => sheet,
+        None => return Ok(())
+    };
+    let transaction = conn.transaction().map_err(database_error)?;
+    create_table(&transaction, sheet_name, &first.types, first.schema.as_deref())?;
+    for (language, sheet) in sheets {
+        insert_rows(&transaction, sheet_name, sheet, *language)?;
+    }
+    transaction.commit().map_err(database_error)
+}
+
+#[cfg(test)]
+mod sqlite_test {
+    use super::*;
+    use super::super::test_sheet;
+    use super::super::ex::{BasicInfo, StringInfo};
+
+    fn sheet(rows: &[(usize, &str, u16)]) -> Sheet {
+        let mut sheet = test_sheet(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 12 }),
+            SheetDataType::UShort(BasicInfo { pointer: 4 }),
+            SheetDataType::Float(BasicInfo { pointer: 8 }),
+        ], rows.iter().map(|(id, name, category)|
+            (*id, vec![SheetValue::String((*name).into()), SheetValue::UShort(*category), SheetValue::Float(0.5)])));
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(r#"{ "sheet": "Item", "definitions": [
+            { "name": "Name" },
+            { "index": 1, "name": "ItemUICategory", "converter": { "type": "link", "target": "ItemUICategory" } },
+            { "index": 2, "name": "name" } ] }"#).unwrap());
+        sheet
+    }
+
+    #[test]
+    fn sheet_to_table() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        export_sheet(&mut conn, "item/Item", &[
+            (SheetLanguage::English, sheet(&[(1, "Potion", 44), (2, "Ether", 44)])),
+            (SheetLanguage::German, sheet(&[(1, "Trank", 44)])),
+        ]).unwrap();
+
+        let rows: Vec<(i64, String, String, i64)> = conn
+            .prepare("SELECT id, language, Name, ItemUICategory FROM item_Item ORDER BY language, id").unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))).unwrap()
+            .map(Result::unwrap).collect();
+        assert_eq!(rows, vec![
+            (1, String::from("de"), String::from("Trank"), 44),
+            (1, String::from("en"), String::from("Potion"), 44),
+            (2, String::from("en"), String::from("Ether"), 44),
+        ]);
+        let columns: Vec<String> = conn.prepare("SELECT name FROM pragma_table_info('item_Item')").unwrap()
+            .query_map([], |r| r.get(0)).unwrap().map(Result::unwrap).collect();
+        assert_eq!(columns, vec!["id", "language", "Name", "ItemUICategory", "name_2"]);
+        let indexes: i64 = conn.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'index' AND tbl_name = 'item_Item' AND sql IS NOT NULL",
+            [], |r| r.get(0)).unwrap();
+        assert_eq!(indexes, 1);
+
+        // Exporting again replaces the table
+        export_sheet(&mut conn, "item/Item", &[(SheetLanguage::None, sheet(&[(3, "Elixir", 1)]))]).unwrap();
+        let count: i64 = conn.query_row("SELECT count(*) FROM item_Item WHERE language = ''", [], |r| r.get(0)).unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,284 @@
+use super::ex::{SheetDataType, SheetInfo, SheetPage};
+use super::decoding::EXDF_MAGIC;
+use byteorder::{BigEndian, ByteOrder};
+
+use std::collections::HashMap;
+use std::fmt;
+
+const EXD_HEADER_SIZE: usize = 0x20;
+
+/// A structural problem found in a sheet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FindingKind {
+    /// The EXH places a column past the end of the fixed-length row data.
+    ColumnOutsideRow { end: usize, data_set_size: u16 },
+    /// Two pages of the EXH cover some of the same row ids.
+    OverlappingPages { other_page: u32 },
+    /// No EXDF data was given for a page of the EXH.
+    MissingPage,
+    PageTooShort { length: usize },
+    MagicMissing,
+    /// The header of the EXDF claims more data than the file holds.
+    TruncatedPage { required: usize, actual: usize },
+    /// The offset table isn't a whole number of entries.
+    MisalignedOffsetTable { size: u32 },
+    /// The offset table lists a row whose id isn't in the page's range.
+    RowOutsidePage,
+    /// A row id listed more than once, in this page or an earlier one.
+    DuplicateRow { first_page: u32 },
+    /// A row's offset isn't inside the data section.
+    RowOffsetOutOfRange { offset: u32 },
+    /// A row's size carries it past the end of the data section.
+    RowOverrun { end: usize, data_end: usize },
+    /// A row is smaller than the fixed-length data every row has.
+    RowTooShort { size: u32, data_set_size: u16 },
+    /// A string cell points past the end of its row's string heap.
+    StringOutOfHeap { offset: usize, size: usize },
+    /// A string cell has no null terminator before the end of the row.
+    UnterminatedString,
+    /// The number of rows found differs from the EXH's row count.
+    RowCountMismatch { expected: u32, found: usize }
+}
+
+/// A problem found while validating a sheet, with where it was found. `page` is the
+/// first row id of the page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub page: Option<u32>,
+    pub row: Option<usize>,
+    pub column: Option<usize>,
+    pub kind: FindingKind
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut location = Vec::new();
+        if let Some(page) = self.page {
+            location.push(format!("page {}", page));
+        }
+        if let Some(row) = self.row {
+            location.push(format!("row {}", row));
+        }
+        if let Some(column) = self.column {
+            location.push(format!("column {}", column));
+        }
+        if !location.is_empty() {
+            write!(f, "{}: ", location.join(", "))?;
+        }
+        match &self.kind {
+            FindingKind::ColumnOutsideRow { end, data_set_size } =>
+                write!(f, "column ends at {}, past the row data size {}", end, data_set_size),
+            FindingKind::OverlappingPages { other_page } => write!(f, "overlaps page {}", other_page),
+            FindingKind::MissingPage => write!(f, "no EXDF data for the page"),
+            FindingKind::PageTooShort { length } => write!(f, "EXDF is {} bytes, shorter than its header", length),
+            FindingKind::MagicMissing => write!(f, "EXDF magic is missing"),
+            FindingKind::TruncatedPage { required, actual } =>
+                write!(f, "EXDF header needs {} bytes but the file has {}", required, actual),
+            FindingKind::MisalignedOffsetTable { size } =>
+                write!(f, "offset table size {} is not a multiple of 8", size),
+            FindingKind::RowOutsidePage => write!(f, "row id is outside the page's range"),
+            FindingKind::DuplicateRow { first_page } => write!(f, "row id was already listed in page {}", first_page),
+            FindingKind::RowOffsetOutOfRange { offset } => write!(f, "row offset {} is outside the data section", offset),
+            FindingKind::RowOverrun { end, data_end } =>
+                write!(f, "row ends at {}, past the end of the data section at {}", end, data_end),
+            FindingKind::RowTooShort { size, data_set_size } =>
+                write!(f, "row is {} bytes, shorter than the row data size {}", size, data_set_size),
+            FindingKind::StringOutOfHeap { offset, size } =>
+                write!(f, "string starts at {}, past the end of the row at {}", offset, size),
+            FindingKind::UnterminatedString => write!(f, "string has no null terminator"),
+            FindingKind::RowCountMismatch { expected, found } =>
+                write!(f, "EXH lists {} rows but the pages hold {}", expected, found)
+        }
+    }
+}
+
+/// Checks the pages of a sheet against its EXH, collecting every problem found rather
+/// than stopping at the first. `exd` holds the EXDF files in the order of `info.pages`.
+pub fn validate_sheet(info: &SheetInfo, exd: &[Vec<u8>]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let finding = |page: Option<u32>, row: Option<usize>, column: Option<usize>, kind: FindingKind| Finding { page, row, column, kind };
+
+    for (column, data_type) in info.data_types.iter().enumerate() {
+        let end = data_type.get_pointer() as usize + data_type.get_size() as usize;
+        if end > info.data_set_size as usize {
+            findings.push(finding(None, None, Some(column), FindingKind::ColumnOutsideRow { end, data_set_size: info.data_set_size }));
+        }
+    }
+    for (i, page) in info.pages.iter().enumerate() {
+        for other in &info.pages[..i] {
+            let (start, end) = (page.page_entry as u64, page.page_entry as u64 + page.page_size as u64);
+            let (other_start, other_end) = (other.page_entry as u64, other.page_entry as u64 + other.page_size as u64);
+            if start < other_end && other_start < end {
+                findings.push(finding(Some(page.page_entry), None, None, FindingKind::OverlappingPages { other_page: other.page_entry }));
+            }
+        }
+    }
+
+    let mut seen = HashMap::<usize, u32>::new();
+    for (i, page) in info.pages.iter().enumerate() {
+        match exd.get(i) {
+            Some(pexd) => validate_page(info, page, pexd, &mut seen, &mut findings),
+            None => findings.push(finding(Some(page.page_entry), None, None, FindingKind::MissingPage))
+        }
+    }
+    if seen.len() != info.num_entries as usize {
+        findings.push(finding(None, None, None, FindingKind::RowCountMismatch { expected: info.num_entries, found: seen.len() }));
+    }
+    findings
+}
+
+fn validate_page(info: &SheetInfo, page: &SheetPage, pexd: &[u8], seen: &mut HashMap<usize, u32>, findings: &mut Vec<Finding>) {
+    let page_finding = |row: Option<usize>, column: Option<usize>, kind: FindingKind| Finding { page: Some(page.page_entry), row, column, kind };
+    if pexd.len() < EXD_HEADER_SIZE {
+        findings.push(page_finding(None, None, FindingKind::PageTooShort { length: pexd.len() }));
+        return;
+    }
+    if BigEndian::read_u32(&pexd[0..4]) != EXDF_MAGIC {
+        findings.push(page_finding(None, None, FindingKind::MagicMissing));
+    }
+    let offset_size = BigEndian::read_u32(&pexd[0x8..0xc]);
+    let data_size = BigEndian::read_u32(&pexd[0xc..0x10]);
+    let data_start = EXD_HEADER_SIZE + offset_size as usize;
+    let data_end = data_start + data_size as usize;
+    if data_end > pexd.len() {
+        findings.push(page_finding(None, None, FindingKind::TruncatedPage { required: data_end, actual: pexd.len() }));
+    }
+    if offset_size % 8 != 0 {
+        findings.push(page_finding(None, None, FindingKind::MisalignedOffsetTable { size: offset_size }));
+    }
+    let data_end = data_end.min(pexd.len());
+    let table_end = data_start.min(pexd.len());
+
+    for entry in pexd[EXD_HEADER_SIZE..table_end].chunks_exact(8) {
+        let id = BigEndian::read_u32(&entry[0..4]) as usize;
+        let offset = BigEndian::read_u32(&entry[4..8]);
+        if !page.contains(id) {
+            findings.push(page_finding(Some(id), None, FindingKind::RowOutsidePage));
+        }
+        if let Some(first_page) = seen.get(&id) {
+            findings.push(page_finding(Some(id), None, FindingKind::DuplicateRow { first_page: *first_page }));
+            continue;
+        }
+        seen.insert(id, page.page_entry);
+
+        let row_start = offset as usize + 6;
+        if (offset as usize) < data_start || row_start > data_end {
+            findings.push(page_finding(Some(id), None, FindingKind::RowOffsetOutOfRange { offset }));
+            continue;
+        }
+        let size = BigEndian::read_u32(&pexd[offset as usize..offset as usize + 4]);
+        let row_end = row_start + size as usize;
+        if row_end > data_end {
+            findings.push(page_finding(Some(id), None, FindingKind::RowOverrun { end: row_end, data_end }));
+            continue;
+        }
+        if size < info.data_set_size as u32 {
+            findings.push(page_finding(Some(id), None, FindingKind::RowTooShort { size, data_set_size: info.data_set_size }));
+            continue;
+        }
+        let row = &pexd[row_start..row_end];
+        for (column, data_type) in info.data_types.iter().enumerate() {
+            if let SheetDataType::String(s_info) = data_type {
+                let pointer = s_info.pointer as usize;
+                if pointer + 4 > info.data_set_size as usize {
+                    continue;
+                }
+                let start = s_info.strings_offset as usize + BigEndian::read_u32(&row[pointer..pointer + 4]) as usize;
+                if start >= row.len() {
+                    findings.push(page_finding(Some(id), Some(column), FindingKind::StringOutOfHeap { offset: start, size: row.len() }));
+                } else if !row[start..].contains(&0) {
+                    findings.push(page_finding(Some(id), Some(column), FindingKind::UnterminatedString));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod validate_test {
+    use super::*;
+    use super::super::SheetValue;
+    use super::super::encoding::{RowBuilder, encode_sheet_pages};
+    use super::super::ex::{BasicInfo, SheetLanguage, StringInfo};
+    use super::super::sestring::SeString;
+    use std::collections::HashSet;
+    use std::rc::Rc;
+
+    fn test_info() -> SheetInfo {
+        let mut languages = HashSet::new();
+        languages.insert(SheetLanguage::None);
+        SheetInfo {
+            data_types: vec![
+                SheetDataType::String(StringInfo { pointer: 0, strings_offset: 8 }),
+                SheetDataType::UInt(BasicInfo { pointer: 4 }),
+            ],
+            pages: vec![SheetPage { page_entry: 0, page_size: 10 }, SheetPage { page_entry: 10, page_size: 10 }],
+            languages,
+            num_entries: 3,
+            data_set_size: 8,
+            variant: 1
+        }
+    }
+
+    fn test_pages(info: &SheetInfo) -> Vec<Vec<u8>> {
+        let types = Rc::new(info.data_types.clone());
+        let rows: Vec<(usize, Vec<u8>)> = [1usize, 2, 12].iter().map(|id| {
+            let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+            builder.set(0, &SheetValue::String(SeString::from("name"))).unwrap();
+            builder.set(1, &SheetValue::UInt(*id as u32)).unwrap();
+            (*id, builder.build())
+        }).collect();
+        encode_sheet_pages(info, rows.iter().map(|(id, by)| (*id, by.as_slice()))).unwrap()
+    }
+
+    /// Gets the offset of the `n`th row of a page from its offset table.
+    fn row_offset(page: &[u8], n: usize) -> usize {
+        BigEndian::read_u32(&page[0x24 + 8 * n..0x28 + 8 * n]) as usize
+    }
+
+    #[test]
+    fn valid_sheet_has_no_findings() {
+        let info = test_info();
+        assert_eq!(validate_sheet(&info, &test_pages(&info)), vec![]);
+    }
+
+    #[test]
+    fn findings_are_collected() {
+        let mut info = test_info();
+        info.data_types.push(SheetDataType::UShort(BasicInfo { pointer: 7 }));
+        let mut pages = test_pages(&info);
+
+        // Row 2: string pointer past the heap
+        let second = row_offset(&pages[0], 1) + 6;
+        BigEndian::write_u32(&mut pages[0][second..second + 4], 100);
+        // Row 1: listed again in page 10, at an offset inside the header
+        let entries = BigEndian::read_u32(&pages[1][0x8..0xc]) as usize;
+        BigEndian::write_u32(&mut pages[1][0x20 + entries - 8..0x20 + entries - 4], 1);
+        BigEndian::write_u32(&mut pages[1][0x20 + entries - 4..0x20 + entries], 4);
+
+        let findings = validate_sheet(&info, &pages);
+        assert_eq!(findings, vec![
+            Finding { page: None, row: None, column: Some(2), kind: FindingKind::ColumnOutsideRow { end: 9, data_set_size: 8 } },
+            Finding { page: Some(0), row: Some(2), column: Some(0), kind: FindingKind::StringOutOfHeap { offset: 108, size: 14 } },
+            Finding { page: Some(10), row: Some(1), column: None, kind: FindingKind::RowOutsidePage },
+            Finding { page: Some(10), row: Some(1), column: None, kind: FindingKind::DuplicateRow { first_page: 0 } },
+            Finding { page: None, row: None, column: None, kind: FindingKind::RowCountMismatch { expected: 3, found: 2 } },
+        ]);
+        assert_eq!(findings[1].to_string(), "page 0, row 2, column 0: string starts at 108, past the end of the row at 14");
+    }
+
+    #[test]
+    fn broken_pages() {
+        let info = test_info();
+        let mut pages = test_pages(&info);
+        let first = row_offset(&pages[0], 0);
+        BigEndian::write_u32(&mut pages[0][first..first + 4], 4);
+        pages[1].truncate(0x2a);
+
+        let findings = validate_sheet(&info, &pages);
+        assert_eq!(findings[0].kind, FindingKind::RowTooShort { size: 4, data_set_size: 8 });
+        assert!(matches!(findings[1].kind, FindingKind::TruncatedPage { .. }));
+        assert!(matches!(findings[2].kind, FindingKind::RowOffsetOutOfRange { .. }));
+        assert_eq!(validate_sheet(&info, &pages[..1])[1].kind, FindingKind::MissingPage);
+    }
+}
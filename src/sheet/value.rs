@@ -0,0 +1,200 @@
+use super::{FromSheet, SheetError, SheetRowRef, SheetErrorType};
+use super::ex::SheetDataType;
+use super::sestring::SeString;
+use super::row_reader::BitFlags;
+
+/// A cell value of any column type, for code that walks sheets without knowing
+/// their layout ahead of time.
+///
+/// Values are `Eq` and `Hash` so they can key maps; floats compare by their bits, so
+/// `NaN` equals itself and `0.0` differs from `-0.0`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SheetValue {
+    String(SeString),
+    Bool(bool),
+    Byte(i8),
+    UByte(u8),
+    Short(i16),
+    UShort(u16),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+    PackedInts(u64),
+    /// The value of the single bit a BitFlags column refers to.
+    BitFlags(bool)
+}
+
+impl SheetValue {
+    /// Gets the value as an integer. Booleans are 0 or 1; strings and floats are not converted.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            SheetValue::Bool(v) | SheetValue::BitFlags(v) => Some(*v as i64),
+            SheetValue::Byte(v) => Some(*v as i64),
+            SheetValue::UByte(v) => Some(*v as i64),
+            SheetValue::Short(v) => Some(*v as i64),
+            SheetValue::UShort(v) => Some(*v as i64),
+            SheetValue::Int(v) => Some(*v as i64),
+            SheetValue::UInt(v) => Some(*v as i64),
+            SheetValue::PackedInts(v) => Some(*v as i64),
+            SheetValue::String(_) | SheetValue::Float(_) => None
+        }
+    }
+
+    /// Gets the value as a float. Every type except strings converts.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            SheetValue::Float(v) => Some(*v as f64),
+            SheetValue::PackedInts(v) => Some(*v as f64),
+            other => other.as_i64().map(|v| v as f64)
+        }
+    }
+
+    /// Converts the value to JSON. Strings use their display form, so macros become `<hex:..>` tags.
+    pub fn to_json(&self) -> ::serde_json::Value {
+        use serde_json::Value;
+        match self {
+            SheetValue::String(v) => Value::from(v.to_string()),
+            SheetValue::Bool(v) | SheetValue::BitFlags(v) => Value::from(*v),
+            SheetValue::Float(v) => Value::from(*v as f64),
+            SheetValue::PackedInts(v) => Value::from(*v),
+            other => Value::from(other.as_i64().unwrap_or_default())
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&SeString> {
+        match self {
+            SheetValue::String(s) => Some(s),
+            _ => None
+        }
+    }
+}
+
+impl PartialEq for SheetValue {
+    fn eq(&self, other: &SheetValue) -> bool {
+        match (self, other) {
+            (SheetValue::String(a), SheetValue::String(b)) => a == b,
+            (SheetValue::Bool(a), SheetValue::Bool(b)) => a == b,
+            (SheetValue::Byte(a), SheetValue::Byte(b)) => a == b,
+            (SheetValue::UByte(a), SheetValue::UByte(b)) => a == b,
+            (SheetValue::Short(a), SheetValue::Short(b)) => a == b,
+            (SheetValue::UShort(a), SheetValue::UShort(b)) => a == b,
+            (SheetValue::Int(a), SheetValue::Int(b)) => a == b,
+            (SheetValue::UInt(a), SheetValue::UInt(b)) => a == b,
+            (SheetValue::Float(a), SheetValue::Float(b)) => a.to_bits() == b.to_bits(),
+            (SheetValue::PackedInts(a), SheetValue::PackedInts(b)) => a == b,
+            (SheetValue::BitFlags(a), SheetValue::BitFlags(b)) => a == b,
+            _ => false
+        }
+    }
+}
+
+impl Eq for SheetValue {}
+
+impl std::hash::Hash for SheetValue {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            SheetValue::String(v) => v.hash(state),
+            SheetValue::Bool(v) | SheetValue::BitFlags(v) => v.hash(state),
+            SheetValue::Byte(v) => v.hash(state),
+            SheetValue::UByte(v) => v.hash(state),
+            SheetValue::Short(v) => v.hash(state),
+            SheetValue::UShort(v) => v.hash(state),
+            SheetValue::Int(v) => v.hash(state),
+            SheetValue::UInt(v) => v.hash(state),
+            SheetValue::Float(v) => v.to_bits().hash(state),
+            SheetValue::PackedInts(v) => v.hash(state)
+        }
+    }
+}
+
+impl std::fmt::Display for SheetValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SheetValue::String(v) => write!(f, "{}", v),
+            SheetValue::Bool(v) | SheetValue::BitFlags(v) => write!(f, "{}", v),
+            SheetValue::Byte(v) => write!(f, "{}", v),
+            SheetValue::UByte(v) => write!(f, "{}", v),
+            SheetValue::Short(v) => write!(f, "{}", v),
+            SheetValue::UShort(v) => write!(f, "{}", v),
+            SheetValue::Int(v) => write!(f, "{}", v),
+            SheetValue::UInt(v) => write!(f, "{}", v),
+            SheetValue::Float(v) => write!(f, "{}", v),
+            SheetValue::PackedInts(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl FromSheet for SheetValue {
+    type Error = SheetError;
+    fn from_ex_data(b: &SheetRowRef, cell: usize) -> Result<Self, Self::Error> {
+        match b.types.get(cell) {
+            Some(get_result) => Ok(match get_result {
+                SheetDataType::String(_) => SheetValue::String(b.read_cell_data(cell)?),
+                SheetDataType::Bool(_) => SheetValue::Bool(b.read_cell_data(cell)?),
+                SheetDataType::Byte(_) => SheetValue::Byte(b.read_cell_data(cell)?),
+                SheetDataType::UByte(_) => SheetValue::UByte(b.read_cell_data(cell)?),
+                SheetDataType::Short(_) => SheetValue::Short(b.read_cell_data(cell)?),
+                SheetDataType::UShort(_) => SheetValue::UShort(b.read_cell_data(cell)?),
+                SheetDataType::Int(_) => SheetValue::Int(b.read_cell_data(cell)?),
+                SheetDataType::UInt(_) => SheetValue::UInt(b.read_cell_data(cell)?),
+                SheetDataType::Float(_) => SheetValue::Float(b.read_cell_data(cell)?),
+                SheetDataType::PackedInts(_) => SheetValue::PackedInts(b.read_cell_data(cell)?),
+                SheetDataType::BitFlags(b_info) =>
+                    SheetValue::BitFlags(b.read_cell_data::<BitFlags>(cell)?.get_bool(b_info.bit)),
+            }),
+            None => Err(Self::Error { error_type: SheetErrorType::CellOutOfBounds })
+        }
+    }
+}
+
+#[cfg(test)]
+mod value_test {
+    use super::*;
+    use super::super::SheetRow;
+    use super::super::ex::{BasicInfo, BitFlagsInfo, StringInfo};
+    use std::rc::Rc;
+
+    #[test]
+    fn dispatch_on_column_type() {
+        let types = Rc::new(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 28 }),
+            SheetDataType::Bool(BasicInfo { pointer: 4 }),
+            SheetDataType::Byte(BasicInfo { pointer: 5 }),
+            SheetDataType::UByte(BasicInfo { pointer: 6 }),
+            SheetDataType::BitFlags(BitFlagsInfo { pointer: 7, bit: 2 }),
+            SheetDataType::Short(BasicInfo { pointer: 8 }),
+            SheetDataType::UShort(BasicInfo { pointer: 10 }),
+            SheetDataType::Int(BasicInfo { pointer: 12 }),
+            SheetDataType::UInt(BasicInfo { pointer: 16 }),
+            SheetDataType::Float(BasicInfo { pointer: 20 }),
+            SheetDataType::PackedInts(BasicInfo { pointer: 20 }),
+        ]);
+        let by = vec![
+            0, 0, 0, 0,
+            1, 0xFF, 0xFE, 0b100,
+            0xFF, 0xFE, 0x01, 0x00,
+            0xFF, 0xFF, 0xFF, 0xFD,
+            0x00, 0x01, 0x00, 0x00,
+            0x42, 0xb4, 0x00, 0x00, 0, 0, 0, 9,
+            b'h', b'i', 0,
+        ];
+        let row = SheetRow { by, types, schema: None };
+        assert_eq!(row.values().unwrap(), vec![
+            SheetValue::String(SeString::from("hi")),
+            SheetValue::Bool(true),
+            SheetValue::Byte(-1),
+            SheetValue::UByte(0xFE),
+            SheetValue::BitFlags(true),
+            SheetValue::Short(-2),
+            SheetValue::UShort(0x100),
+            SheetValue::Int(-3),
+            SheetValue::UInt(0x10000),
+            SheetValue::Float(90.0),
+            SheetValue::PackedInts(0x42b4_0000_0000_0009),
+        ]);
+        let value: SheetValue = row.get(6).unwrap();
+        assert_eq!(value.as_i64(), Some(0x100));
+        assert!(row.get::<SheetValue>(11).is_err());
+    }
+}
@@ -323,3 +323,332 @@ mod expack_test {
     }
 }
 
+
+#[cfg(test)]
+mod export_test {
+    use super::super::*;
+    use sheet::ex::{BasicInfo, SheetDataType, StringInfo};
+    use sheet::schema::SheetSchema;
+    use sheet::{Sheet, SheetRow};
+    use std::rc::Rc;
+
+    /// A small in-memory sheet with a string, a signed and a float column.
+    pub fn test_sheet() -> Sheet {
+        let types = Rc::new(vec![
+            SheetDataType::String(StringInfo { pointer: 0, strings_offset: 12 }),
+            SheetDataType::Short(BasicInfo { pointer: 4 }),
+            SheetDataType::Float(BasicInfo { pointer: 8 }),
+        ]);
+        let mut sheet = Sheet { rows: Default::default(), types: types.clone(), column_count: 3, schema: None };
+        let mut first = vec![0, 0, 0, 0, 0xFF, 0xFE, 0, 0, 0x3f, 0xc0, 0, 0];
+        first.extend_from_slice(b"Say \"hi\",\nthen go\0");
+        sheet.rows.insert(3, SheetRow { by: first, types: types.clone(), schema: None });
+        let mut second = vec![0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0];
+        second.extend_from_slice(b"plain\0");
+        sheet.rows.insert(10, SheetRow { by: second, types: types.clone(), schema: None });
+        sheet
+    }
+
+    #[test]
+    fn json_export() {
+        let mut sheet = test_sheet();
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(
+            r#"{ "sheet": "Test", "definitions": [ { "name": "Text" }, { "index": 2, "name": "Scale" } ] }"#).unwrap());
+        let mut out = Vec::<u8>::new();
+        sheet::write_json(&sheet, &mut out).unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed, serde_json::json!([
+            { "id": 3, "Text": "Say \"hi\",\nthen go", "1": -2, "Scale": 1.5 },
+            { "id": 10, "Text": "plain", "1": 7, "Scale": 0.0 }
+        ]));
+    }
+
+    #[test]
+    fn ndjson_export() {
+        let sheet = test_sheet();
+        let mut out = Vec::<u8>::new();
+        sheet::write_ndjson(&sheet, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], r#"{"id":10,"0":"plain","1":7,"2":0.0}"#);
+    }
+
+    #[test]
+    fn csv_export_escapes_fields() {
+        let sheet = test_sheet();
+        let mut out = Vec::<u8>::new();
+        sheet::write_csv(&sheet, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "\"index\",\"string\",\"int16\",\"float\"\n\
+             \"3\",\"Say \"\"hi\"\",\nthen go\",\"-2\",\"1.5\"\n\
+             \"10\",\"plain\",\"7\",\"0\"\n");
+    }
+
+    #[test]
+    fn csv_export_dialects() {
+        use sheet::csv::{CsvOptions, HeaderStyle, QuoteStyle};
+        let mut sheet = test_sheet();
+        sheet.set_schema(&SheetSchema::from_saint_coinach_json(
+            r#"{ "sheet": "Test", "definitions": [ { "name": "Text" } ] }"#).unwrap());
+
+        let mut out = Vec::<u8>::new();
+        let options = CsvOptions { delimiter: ';', quoting: QuoteStyle::Necessary, header: HeaderStyle::Indices, ..CsvOptions::default() };
+        sheet::csv::write_csv_with_options(&sheet, &mut out, &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "index;0;1;2\n3;\"Say \"\"hi\"\",\nthen go\";-2;1.5\n10;plain;7;0\n");
+
+        let mut out = Vec::<u8>::new();
+        sheet::csv::write_csv_with_options(&sheet, &mut out, &CsvOptions::saint_coinach()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+            "key,0,1,2\r\n#,Text,,\r\nint32,str,int16,single\r\n\
+             3,\"Say \"\"hi\"\",\nthen go\",-2,1.5\r\n10,\"plain\",7,0\r\n");
+    }
+
+    fn test_sheet_info(sheet: &Sheet) -> sheet::ex::SheetInfo {
+        let mut languages = std::collections::HashSet::new();
+        languages.insert(sheet::ex::SheetLanguage::English);
+        sheet::ex::SheetInfo {
+            data_types: sheet.types.to_vec(),
+            pages: vec![sheet::ex::SheetPage { page_entry: 0, page_size: 20 }],
+            languages,
+            num_entries: 2,
+            data_set_size: 12,
+            variant: 1
+        }
+    }
+
+    #[test]
+    fn csv_import_round_trip() {
+        use sheet::csv::CsvOptions;
+        use sheet::decoding::decode_sheet_from_bytes;
+        let sheet = test_sheet();
+        let info = test_sheet_info(&sheet);
+        for options in &[CsvOptions::default(), CsvOptions::saint_coinach()] {
+            let mut out = Vec::<u8>::new();
+            sheet::csv::write_csv_with_options(&sheet, &mut out, options).unwrap();
+            let pages = sheet::csv::import_csv(&mut out.as_slice(), &info, options).unwrap();
+            let imported = decode_sheet_from_bytes(&info, &pages).unwrap();
+            assert_eq!(imported.rows.keys().collect::<Vec<_>>(), vec![&3, &10]);
+            for (id, row) in sheet.rows.iter() {
+                assert_eq!(imported.rows[id].values().unwrap(), row.values().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn csv_import_errors() {
+        use sheet::csv::{CsvImportError, CsvOptions};
+        let sheet = test_sheet();
+        let info = test_sheet_info(&sheet);
+        let import = |text: &str| sheet::csv::import_csv(&mut text.as_bytes(), &info, &CsvOptions::default());
+
+        match import("index,a,b,c\n3,\"x\ny\",1,2\n4,\"z\",70000,2\n") {
+            Err(CsvImportError::Cell { line, column, .. }) => assert_eq!((line, column), (4, 1)),
+            other => panic!("unexpected result {:?}", other)
+        }
+        match import("index,a,b,c\n3,x,1\n") {
+            Err(CsvImportError::Record { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected result {:?}", other)
+        }
+        match import("index,a,b,c\n3,x,1,2\n4,y,1,2\n3,z,1,2\n") {
+            Err(CsvImportError::Record { line, message }) => {
+                assert_eq!(line, 4);
+                assert!(message.contains("more than once"));
+            },
+            other => panic!("unexpected result {:?}", other)
+        }
+        match import("index,a,b,c\n3,\"x,1,2\n") {
+            Err(CsvImportError::Syntax { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(matches!(import("index,a,b,c\n30,x,1,2\n"), Err(CsvImportError::Encoding(_))));
+
+        // Headers written for a different column layout
+        match import("index,a,b\n3,x,1,2\n") {
+            Err(CsvImportError::Record { line, .. }) => assert_eq!(line, 1),
+            other => panic!("unexpected result {:?}", other)
+        }
+        let saint_coinach = "key,0,1,2\r\n#,,,\r\nint32,str,int16,int32\r\n3,x,1,2\r\n";
+        match sheet::csv::import_csv(&mut saint_coinach.as_bytes(), &info, &CsvOptions::saint_coinach()) {
+            Err(CsvImportError::Record { line, message }) => {
+                assert_eq!(line, 3);
+                assert!(message.contains("\"int32\" where the sheet has \"single\""), "{}", message);
+            },
+            other => panic!("unexpected result {:?}", other)
+        }
+        let types = CsvOptions { header: sheet::csv::HeaderStyle::Types, ..CsvOptions::default() };
+        assert!(sheet::csv::import_csv(&mut "index,str,str,str\n3,x,1,2\n".as_bytes(), &info, &types).is_err());
+    }
+}
+
+#[cfg(test)]
+mod cache_test {
+    use super::super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+    use sheet::cache::SheetCache;
+    use sheet::encoding::{RowBuilder, encode_sheet_info, encode_sheet_pages};
+    use sheet::ex::{BasicInfo, SheetDataType, SheetInfo, SheetLanguage, SheetPage};
+    use sheet::schema::SchemaSet;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    /// Writes `files` into `ffxiv/0a0000.win32.index` and `.dat0` under `sqpack`, each
+    /// stored as a single uncompressed block.
+    fn write_sqpack(sqpack: &Path, files: &[(String, Vec<u8>)]) {
+        let mut dat = Vec::<u8>::new();
+        let mut folders = BTreeMap::<u32, Vec<(u32, u32)>>::new();
+        for (path, data) in files {
+            let hash = hash::compute_path(path);
+            // Data offsets are stored in units of 8 with the low bits holding the dat number
+            dat.resize(dat.len().div_ceil(0x80) * 0x80, 0);
+            folders.entry(hash.folder_hash).or_default().push((hash.file_hash, (dat.len() >> 3) as u32));
+            let mut header = [0u8; 0x80];
+            LittleEndian::write_u32_into(&[0x80, 2, data.len() as u32, 0, data.len() as u32, 1], &mut header[..24]);
+            LittleEndian::write_u32(&mut header[24..], 0);
+            LittleEndian::write_u16(&mut header[28..], (data.len() + 0x10) as u16);
+            LittleEndian::write_u16(&mut header[30..], data.len() as u16);
+            dat.extend_from_slice(&header);
+            let mut block = [0u8; 0x10];
+            LittleEndian::write_u32_into(&[0x10, 0, 32000, data.len() as u32], &mut block);
+            dat.extend_from_slice(&block);
+            dat.extend_from_slice(data);
+        }
+
+        let mut index = vec![0u8; 0x800];
+        index[..8].copy_from_slice(b"SqPack\0\0");
+        LittleEndian::write_u32(&mut index[0x0c..], 0x400);
+        let mut folder_table = Vec::<u8>::new();
+        for (folder_hash, entries) in &folders {
+            let mut folder = [0u8; 0x10];
+            LittleEndian::write_u32_into(&[*folder_hash, index.len() as u32, entries.len() as u32 * 0x10, 0], &mut folder);
+            folder_table.extend_from_slice(&folder);
+            for (file_hash, offset) in entries {
+                let mut file = [0u8; 0x10];
+                LittleEndian::write_u32_into(&[*file_hash, *folder_hash, *offset, 0], &mut file);
+                index.extend_from_slice(&file);
+            }
+        }
+        let folders_offset = index.len() as u32;
+        LittleEndian::write_u32(&mut index[0x400 + 0xe4..], folders_offset);
+        LittleEndian::write_u32(&mut index[0x400 + 0xe8..], folder_table.len() as u32);
+        index.extend_from_slice(&folder_table);
+
+        std::fs::create_dir_all(sqpack.join("ffxiv")).unwrap();
+        std::fs::write(sqpack.join("ffxiv/0a0000.win32.index"), index).unwrap();
+        std::fs::write(sqpack.join("ffxiv/0a0000.win32.dat0"), dat).unwrap();
+    }
+
+    fn ushort_sheet_info(rows: usize) -> SheetInfo {
+        SheetInfo {
+            data_types: vec![SheetDataType::UShort(BasicInfo { pointer: 0 })],
+            pages: vec![SheetPage { page_entry: 0, page_size: 100 }],
+            languages: vec![SheetLanguage::English].into_iter().collect(),
+            num_entries: rows as u32,
+            data_set_size: 4,
+            variant: 1
+        }
+    }
+
+    /// The EXH and English EXD of a sheet with the given raw rows.
+    #[cfg(feature = "sqlite")]
+    fn raw_sheet_files(name: &str, info: &SheetInfo, rows: &[(usize, Vec<u8>)]) -> Vec<(String, Vec<u8>)> {
+        let page = encode_sheet_pages(info, rows.iter().map(|(id, by)| (*id, by.as_slice()))).unwrap().remove(0);
+        vec![
+            (format!("exd/{}.exh", name), encode_sheet_info(info).unwrap()),
+            (sheet::ex::page_file_name(name, &info.pages[0], SheetLanguage::English), page),
+        ]
+    }
+
+    /// The EXH and English EXD of a sheet of rows with one ushort column.
+    fn sheet_files(name: &str, rows: &[(usize, u16)]) -> Vec<(String, Vec<u8>)> {
+        language_sheet_files(name, &[(SheetLanguage::English, rows)])
+    }
+
+    /// The EXH and EXDs of a sheet with one ushort column, with rows in each language.
+    fn language_sheet_files(name: &str, languages: &[(SheetLanguage, &[(usize, u16)])]) -> Vec<(String, Vec<u8>)> {
+        let mut info = ushort_sheet_info(languages[0].1.len());
+        info.languages = languages.iter().map(|(language, _)| *language).collect();
+        let types = Rc::new(info.data_types.clone());
+        let mut files = vec![(format!("exd/{}.exh", name), encode_sheet_info(&info).unwrap())];
+        for (language, rows) in languages {
+            let built: Vec<(usize, Vec<u8>)> = rows.iter().map(|(id, value)| {
+                let mut builder = RowBuilder::new(types.clone(), info.data_set_size);
+                builder.set(0, &sheet::SheetValue::UShort(*value)).unwrap();
+                (*id, builder.build())
+            }).collect();
+            let page = encode_sheet_pages(&info, built.iter().map(|(id, by)| (*id, by.as_slice()))).unwrap().remove(0);
+            files.push((sheet::ex::page_file_name(name, &info.pages[0], *language), page));
+        }
+        files
+    }
+
+    #[test]
+    fn cached_sheets_resolve_links() {
+        let sqpack = std::env::temp_dir().join(format!("sqpack_blue_cache_test_{}", std::process::id()));
+        let mut files = sheet_files("Recipe", &[(1, 9)]);
+        files.extend(sheet_files("Item", &[(5, 50), (9, 90)]));
+        write_sqpack(&sqpack, &files);
+
+        let mut ffxiv = FFXIV::new(&sqpack).unwrap();
+        ffxiv.set_schemas(SchemaSet::from_saint_coinach_ex_json(r#"{ "sheets": [ { "sheet": "Recipe", "definitions": [
+            { "name": "ItemResult", "converter": { "type": "link", "target": "Item" } } ] } ] }"#).unwrap());
+        let cache = SheetCache::new(&ffxiv);
+        let recipes = cache.get("Recipe").unwrap();
+        assert!(Rc::ptr_eq(&recipes, &cache.get("recipe").unwrap()));
+
+        let item = recipes.rows[&1].resolve("ItemResult", &cache).unwrap().unwrap();
+        assert_eq!((item.sheet_name.as_str(), item.id), ("Item", 9));
+        assert_eq!(item.row().get::<u16>(0).unwrap(), 90);
+        assert!(cache.get("Missing").is_err());
+
+        fn send_and_sync<T: Send + Sync>(_: &T) {}
+        send_and_sync(&ffxiv);
+        std::fs::remove_dir_all(&sqpack).unwrap();
+    }
+
+    #[test]
+    fn diffs_compare_a_shared_language() {
+        let root = std::env::temp_dir().join(format!("sqpack_blue_diff_test_{}", std::process::id()));
+        let mut old_files = vec![(String::from("exd/root.exl"), b"EXLT,2\nItem,1\n".to_vec())];
+        old_files.extend(sheet_files("Item", &[(1, 10)]));
+        write_sqpack(&root.join("old"), &old_files);
+        // The patch adds German, which the preferences list first
+        let mut new_files = vec![(String::from("exd/root.exl"), b"EXLT,2\nItem,1\n".to_vec())];
+        new_files.extend(language_sheet_files("Item", &[
+            (SheetLanguage::English, &[(1, 10)]), (SheetLanguage::German, &[(1, 20)])]));
+        write_sqpack(&root.join("new"), &new_files);
+
+        let (old, new) = (FFXIV::new(&root.join("old")).unwrap(), FFXIV::new(&root.join("new")).unwrap());
+        let languages = [SheetLanguage::German, SheetLanguage::English];
+        assert!(old.diff_sheet(&new, "Item", &languages).unwrap().is_empty());
+        let report = old.diff_all_sheets(&new, &languages).unwrap();
+        assert!(report.sheets.is_empty() && report.failed.is_empty());
+        assert!(old.diff_sheet(&new, "Item", &[SheetLanguage::German]).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn export_sqlite_skips_sheets_that_fail_to_insert() {
+        use sheet::ex::StringInfo;
+        let sqpack = std::env::temp_dir().join(format!("sqpack_blue_export_test_{}", std::process::id()));
+        let mut files = vec![(String::from("exd/root.exl"), b"EXLT,2\nItem,1\nBroken,2\n".to_vec())];
+        files.extend(sheet_files("Item", &[(5, 50), (9, 90)]));
+        // The string points past the end of the row, so the page decodes but the row can't be read
+        let mut broken = ushort_sheet_info(1);
+        broken.data_types = vec![SheetDataType::String(StringInfo { pointer: 0, strings_offset: 4 })];
+        files.extend(raw_sheet_files("Broken", &broken, &[(1, vec![0, 0, 0, 100, 0])]));
+        write_sqpack(&sqpack, &files);
+
+        let ffxiv = FFXIV::new(&sqpack).unwrap();
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let skipped = ffxiv.export_sqlite(&mut conn, &[SheetLanguage::English]).unwrap();
+        assert_eq!(skipped.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["Broken"]);
+        let items: i64 = conn.query_row("SELECT count(*) FROM Item", [], |r| r.get(0)).unwrap();
+        assert_eq!(items, 2);
+        let broken: i64 = conn.query_row("SELECT count(*) FROM sqlite_master WHERE name = 'Broken'", [], |r| r.get(0)).unwrap();
+        assert_eq!(broken, 0);
+        std::fs::remove_dir_all(&sqpack).unwrap();
+    }
+}